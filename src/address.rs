@@ -0,0 +1,516 @@
+use std::ops::Range;
+
+use nu_protocol::{LabeledError, Record, Span, Value};
+
+/// One of the addresses of a D-Bus server, which are tried in order until one works
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    pub transport: Transport,
+    /// The GUID the server must have
+    pub guid: Option<String>,
+}
+
+/// How to connect to the server, with the decoded values of the keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    Unix(UnixSocket),
+    Tcp(Tcp),
+    NonceTcp {
+        tcp: Tcp,
+        noncefile: Option<String>,
+    },
+    /// Run a program, and talk to it through its stdin and stdout
+    UnixExec {
+        path: String,
+        argv: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnixSocket {
+    Path(String),
+    Abstract(String),
+    /// A directory to create a socket in (servers only)
+    Dir(String),
+    /// Like [UnixSocket::Dir], but may use an abstract socket instead (servers only)
+    Tmpdir(String),
+    /// The `bus` socket in `$XDG_RUNTIME_DIR` (servers only)
+    Runtime,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tcp {
+    pub host: Option<String>,
+    pub bind: Option<String>,
+    pub port: Option<u16>,
+    pub family: Option<Family>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    Ipv4,
+    Ipv6,
+}
+
+/// A problem with an address, and the byte range of the part that's wrong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressError {
+    pub msg: String,
+    pub label: String,
+    pub range: Range<usize>,
+}
+
+/// A `key=value` pair of an address, with the value decoded
+#[derive(Debug)]
+struct Pair {
+    key: String,
+    value: String,
+    key_range: Range<usize>,
+    value_range: Range<usize>,
+}
+
+/// The pairs of one address, which are taken out as they are recognized
+struct Pairs {
+    transport: String,
+    transport_range: Range<usize>,
+    pairs: Vec<Pair>,
+}
+
+/// Parse a semicolon-separated list of addresses
+pub fn parse(address: &str) -> Result<Vec<Address>, AddressError> {
+    let mut addresses = vec![];
+    let mut start = 0;
+    for entry in address.split(';') {
+        let offset = start;
+        start += entry.len() + 1;
+        // Allows a trailing semicolon, like libdbus does
+        if !entry.is_empty() {
+            addresses.push(parse_entry(entry, offset)?);
+        }
+    }
+    if addresses.is_empty() {
+        return Err(AddressError {
+            msg: "Empty D-Bus address".into(),
+            label: "expected `transport:key=value,...`".into(),
+            range: 0..address.len(),
+        });
+    }
+    Ok(addresses)
+}
+
+/// Parse a list of addresses, labelling the wrong part of it if `span` covers exactly the
+/// address, with or without quotes
+pub fn parse_spanned(address: &str, span: Span) -> Result<Vec<Address>, LabeledError> {
+    parse(address).map_err(|err| {
+        let offset = match span.end - span.start {
+            len if len == address.len() => Some(span.start),
+            len if len == address.len() + 2 => Some(span.start + 1),
+            _ => None,
+        };
+        let span = offset
+            .map(|offset| Span::new(offset + err.range.start, offset + err.range.end))
+            .unwrap_or(span);
+        LabeledError::new(err.msg).with_label(err.label, span)
+    })
+}
+
+fn parse_entry(entry: &str, offset: usize) -> Result<Address, AddressError> {
+    let error = |msg: &str, label: String, range: Range<usize>| AddressError {
+        msg: msg.into(),
+        label,
+        range: offset + range.start..offset + range.end,
+    };
+    let Some((transport, rest)) = entry.split_once(':') else {
+        return Err(error(
+            "Missing transport in D-Bus address",
+            "expected `transport:key=value,...`".into(),
+            0..entry.len(),
+        ));
+    };
+    let mut pairs = Pairs {
+        transport: transport.into(),
+        transport_range: offset..offset + transport.len(),
+        pairs: vec![],
+    };
+
+    let mut start = transport.len() + 1;
+    for pair in rest.split(',').filter(|_| !rest.is_empty()) {
+        let range = start..start + pair.len();
+        start = range.end + 1;
+        let Some((key, value)) = pair.split_once('=') else {
+            return Err(error(
+                "Missing value in D-Bus address",
+                "expected `key=value`".into(),
+                range,
+            ));
+        };
+        let key_range = range.start..range.start + key.len();
+        let value_range = key_range.end + 1..range.end;
+        if key.is_empty() {
+            return Err(error(
+                "Missing key in D-Bus address",
+                "expected `key=value`".into(),
+                range,
+            ));
+        }
+        if pairs.pairs.iter().any(|pair| pair.key == key) {
+            return Err(error(
+                "Duplicate key in D-Bus address",
+                format!("`{key}` was already given"),
+                key_range,
+            ));
+        }
+        pairs.pairs.push(Pair {
+            key: key.into(),
+            value: unescape(value, offset + value_range.start)?,
+            key_range: offset + key_range.start..offset + key_range.end,
+            value_range: offset + value_range.start..offset + value_range.end,
+        });
+    }
+
+    let guid = pairs.take("guid").map(parse_guid).transpose()?;
+    let transport = match transport {
+        "unix" => Transport::Unix(parse_unix(&mut pairs)?),
+        "tcp" => Transport::Tcp(parse_tcp(&mut pairs)?),
+        "nonce-tcp" => Transport::NonceTcp {
+            tcp: parse_tcp(&mut pairs)?,
+            noncefile: pairs.take("noncefile").map(|pair| pair.value),
+        },
+        "unixexec" => parse_unixexec(&mut pairs)?,
+        _ => {
+            return Err(error(
+                "Unknown transport in D-Bus address",
+                "expected `unix`, `tcp`, `nonce-tcp` or `unixexec`".into(),
+                0..transport.len(),
+            ))
+        }
+    };
+    pairs.finish()?;
+    Ok(Address { transport, guid })
+}
+
+fn parse_unix(pairs: &mut Pairs) -> Result<UnixSocket, AddressError> {
+    let mut found: Option<(UnixSocket, Pair)> = None;
+    for key in ["path", "abstract", "dir", "tmpdir", "runtime"] {
+        let Some(pair) = pairs.take(key) else {
+            continue;
+        };
+        if let Some((_, first)) = &found {
+            return Err(AddressError {
+                msg: "Conflicting keys in D-Bus address".into(),
+                label: format!("can't be combined with `{}`", first.key),
+                range: pair.key_range,
+            });
+        }
+        let socket = match key {
+            "path" => UnixSocket::Path(pair.value.clone()),
+            "abstract" => UnixSocket::Abstract(pair.value.clone()),
+            "dir" => UnixSocket::Dir(pair.value.clone()),
+            "tmpdir" => UnixSocket::Tmpdir(pair.value.clone()),
+            _ if pair.value == "yes" => UnixSocket::Runtime,
+            _ => {
+                return Err(AddressError {
+                    msg: "Invalid value in D-Bus address".into(),
+                    label: "`runtime` can only be `yes`".into(),
+                    range: pair.value_range,
+                })
+            }
+        };
+        found = Some((socket, pair));
+    }
+    found.map(|(socket, _)| socket).ok_or_else(|| AddressError {
+        msg: "Incomplete unix address".into(),
+        label: "needs one of `path`, `abstract`, `dir`, `tmpdir` or `runtime`".into(),
+        range: pairs.transport_range.clone(),
+    })
+}
+
+fn parse_tcp(pairs: &mut Pairs) -> Result<Tcp, AddressError> {
+    Ok(Tcp {
+        host: pairs.take("host").map(|pair| pair.value),
+        bind: pairs.take("bind").map(|pair| pair.value),
+        port: pairs
+            .take("port")
+            .map(|pair| {
+                pair.value.parse().map_err(|_| AddressError {
+                    msg: "Invalid port in D-Bus address".into(),
+                    label: "expected a number from 0 to 65535".into(),
+                    range: pair.value_range,
+                })
+            })
+            .transpose()?,
+        family: pairs
+            .take("family")
+            .map(|pair| match &pair.value[..] {
+                "ipv4" => Ok(Family::Ipv4),
+                "ipv6" => Ok(Family::Ipv6),
+                _ => Err(AddressError {
+                    msg: "Invalid address family in D-Bus address".into(),
+                    label: "expected `ipv4` or `ipv6`".into(),
+                    range: pair.value_range,
+                }),
+            })
+            .transpose()?,
+    })
+}
+
+fn parse_unixexec(pairs: &mut Pairs) -> Result<Transport, AddressError> {
+    let path = pairs.take("path").ok_or_else(|| AddressError {
+        msg: "Incomplete unixexec address".into(),
+        label: "needs `path`".into(),
+        range: pairs.transport_range.clone(),
+    })?;
+    let argv0 = pairs.take("argv0");
+    let mut argv = vec![argv0.map_or_else(|| path.value.clone(), |pair| pair.value)];
+    while let Some(pair) = pairs.take(&format!("argv{}", argv.len())) {
+        argv.push(pair.value);
+    }
+    // Any argument left over comes after a gap
+    if let Some(pair) = pairs.pairs.iter().find(|pair| {
+        pair.key
+            .strip_prefix("argv")
+            .is_some_and(|n| n.parse::<u32>().is_ok())
+    }) {
+        return Err(AddressError {
+            msg: "Missing argument in D-Bus address".into(),
+            label: format!("`{}` is given without `argv{}`", pair.key, argv.len()),
+            range: pair.key_range.clone(),
+        });
+    }
+    Ok(Transport::UnixExec {
+        path: path.value,
+        argv,
+    })
+}
+
+fn parse_guid(pair: Pair) -> Result<String, AddressError> {
+    if pair.value.len() == 32 && pair.value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Ok(pair.value)
+    } else {
+        Err(AddressError {
+            msg: "Invalid GUID in D-Bus address".into(),
+            label: "expected 32 hex digits".into(),
+            range: pair.value_range,
+        })
+    }
+}
+
+impl Pairs {
+    fn take(&mut self, key: &str) -> Option<Pair> {
+        let index = self.pairs.iter().position(|pair| pair.key == key)?;
+        Some(self.pairs.remove(index))
+    }
+
+    /// Check that all of the keys were recognized
+    fn finish(self) -> Result<(), AddressError> {
+        match self.pairs.into_iter().next() {
+            Some(pair) => Err(AddressError {
+                msg: "Unknown key in D-Bus address".into(),
+                label: format!("`{}` isn't used by {} addresses", pair.key, self.transport),
+                range: pair.key_range,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether a byte can appear in a value without being percent-escaped
+fn is_optionally_escaped(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"-_/.\\*".contains(&byte)
+}
+
+/// Percent-escape a value, so that it can be used in an address
+pub fn escape(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            _ if is_optionally_escaped(byte) => char::from(byte).to_string(),
+            _ => format!("%{byte:02x}"),
+        })
+        .collect()
+}
+
+/// Decode the percent-escapes in a value, which starts at `offset` in the address
+fn unescape(value: &str, offset: usize) -> Result<String, AddressError> {
+    let mut bytes = vec![];
+    let mut chars = value.char_indices();
+    while let Some((index, ch)) = chars.next() {
+        if ch == '%' {
+            let hex = value
+                .get(index + 1..index + 3)
+                .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()));
+            let Some(hex) = hex else {
+                return Err(AddressError {
+                    msg: "Invalid escape in D-Bus address".into(),
+                    label: "`%` must be followed by two hex digits".into(),
+                    range: offset + index..offset + (index + 3).min(value.len()),
+                });
+            };
+            bytes.push(u8::from_str_radix(hex, 16).expect("checked to be hex"));
+            chars.nth(1);
+        } else if ch.is_ascii() && is_optionally_escaped(ch as u8) {
+            bytes.push(ch as u8);
+        } else {
+            let mut buf = [0; 4];
+            let escaped = ch
+                .encode_utf8(&mut buf)
+                .bytes()
+                .map(|b| format!("%{b:02x}"));
+            return Err(AddressError {
+                msg: "Unescaped character in D-Bus address".into(),
+                label: format!("write this as `{}`", escaped.collect::<String>()),
+                range: offset + index..offset + index + ch.len_utf8(),
+            });
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| AddressError {
+        msg: "Invalid UTF-8 in D-Bus address".into(),
+        label: "the escaped bytes aren't valid UTF-8".into(),
+        range: offset..offset + value.len(),
+    })
+}
+
+impl Address {
+    /// Describe the address as a record, with a column for each key that was given
+    pub fn to_value(&self, span: Span) -> Value {
+        let string = |s: &str| Value::string(s, span);
+        let mut record = Record::new();
+        match &self.transport {
+            Transport::Unix(socket) => {
+                record.push("transport", string("unix"));
+                match socket {
+                    UnixSocket::Path(path) => record.push("path", string(path)),
+                    UnixSocket::Abstract(name) => record.push("abstract", string(name)),
+                    UnixSocket::Dir(dir) => record.push("dir", string(dir)),
+                    UnixSocket::Tmpdir(dir) => record.push("tmpdir", string(dir)),
+                    UnixSocket::Runtime => record.push("runtime", Value::bool(true, span)),
+                }
+            }
+            Transport::Tcp(tcp) => {
+                record.push("transport", string("tcp"));
+                tcp.push_to(&mut record, span);
+            }
+            Transport::NonceTcp { tcp, noncefile } => {
+                record.push("transport", string("nonce-tcp"));
+                tcp.push_to(&mut record, span);
+                if let Some(noncefile) = noncefile {
+                    record.push("noncefile", string(noncefile));
+                }
+            }
+            Transport::UnixExec { path, argv } => {
+                record.push("transport", string("unixexec"));
+                record.push("path", string(path));
+                record.push(
+                    "argv",
+                    Value::list(argv.iter().map(|arg| string(arg)).collect(), span),
+                );
+            }
+        }
+        if let Some(guid) = &self.guid {
+            record.push("guid", string(guid));
+        }
+        Value::record(record, span)
+    }
+}
+
+impl Tcp {
+    fn push_to(&self, record: &mut Record, span: Span) {
+        if let Some(host) = &self.host {
+            record.push("host", Value::string(host, span));
+        }
+        if let Some(bind) = &self.bind {
+            record.push("bind", Value::string(bind, span));
+        }
+        if let Some(port) = self.port {
+            record.push("port", Value::int(port.into(), span));
+        }
+        if let Some(family) = self.family {
+            let family = match family {
+                Family::Ipv4 => "ipv4",
+                Family::Ipv6 => "ipv6",
+            };
+            record.push("family", Value::string(family, span));
+        }
+    }
+}
+
+#[test]
+fn test_parse_transports() {
+    assert_eq!(
+        Ok(vec![
+            Address {
+                transport: Transport::Unix(UnixSocket::Path("/run/user/1000/bus".into())),
+                guid: None,
+            },
+            Address {
+                transport: Transport::Tcp(Tcp {
+                    host: Some("localhost".into()),
+                    port: Some(4000),
+                    family: Some(Family::Ipv4),
+                    ..Tcp::default()
+                }),
+                guid: Some("0123456789abcdef0123456789abcdef".into()),
+            },
+        ]),
+        parse(
+            "unix:path=/run/user/1000/bus;\
+                tcp:host=localhost,port=4000,family=ipv4,guid=0123456789abcdef0123456789abcdef;"
+        )
+    );
+    assert_eq!(
+        Ok(Transport::Unix(UnixSocket::Runtime)),
+        parse("unix:runtime=yes").map(|a| a[0].transport.clone())
+    );
+    assert_eq!(
+        Ok(Transport::NonceTcp {
+            tcp: Tcp::default(),
+            noncefile: Some("/tmp/nonce".into()),
+        }),
+        parse("nonce-tcp:noncefile=/tmp/nonce").map(|a| a[0].transport.clone())
+    );
+    assert_eq!(
+        Ok(Transport::UnixExec {
+            path: "/usr/bin/ssh".into(),
+            argv: vec!["/usr/bin/ssh".into(), "host".into(), "dbus bridge".into()],
+        }),
+        parse("unixexec:path=/usr/bin/ssh,argv2=dbus%20bridge,argv1=host")
+            .map(|a| a[0].transport.clone())
+    );
+}
+
+#[test]
+fn test_parse_escapes() {
+    assert_eq!(
+        Ok(Transport::Unix(UnixSocket::Abstract("my bus/ü".into()))),
+        parse("unix:abstract=my%20bus/%c3%bc").map(|a| a[0].transport.clone())
+    );
+    let err = parse("unix:path=/tmp/my bus").unwrap_err();
+    assert_eq!("write this as `%20`", err.label);
+    assert_eq!(17..18, err.range);
+    assert_eq!(12..14, parse("unix:path=/a%2").unwrap_err().range);
+    assert!(parse("unix:path=%ff").is_err());
+
+    let command = "ssh host 'systemd-stdio-bridge; echo ü'";
+    assert_eq!(
+        "ssh%20host%20%27systemd-stdio-bridge%3b%20echo%20%c3%bc%27",
+        escape(command)
+    );
+    assert_eq!(Ok(command.into()), unescape(&escape(command), 0));
+}
+
+#[test]
+fn test_parse_errors() {
+    let range = |address: &str| parse(address).unwrap_err().range;
+    // The range points at the part that's wrong
+    assert_eq!(0..13, range("/run/dbus/bus;"));
+    assert_eq!(5..8, range("unix:pat"));
+    assert_eq!(13..21, range("unix:path=/a,abstract=b"));
+    assert_eq!(13..17, range("unix:path=/a,path=/b"));
+    assert_eq!(0..4, range("unix:"));
+    assert_eq!(13..15, range("unix:runtime=no"));
+    assert_eq!(24..28, range("tcp:host=localhost,port=lots"));
+    assert_eq!(24..29, range("unixexec:path=/bin/true,argv2=x"));
+    assert_eq!(0..4, range("quic:host=localhost"));
+    assert_eq!(18..21, range("unix:path=/a,guid=abc"));
+    assert!(parse("").is_err());
+}
@@ -0,0 +1,464 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, FixedOffset};
+use dbus::Message;
+use nu_protocol::{record, LabeledError, Record, Span, Value};
+
+use crate::convert;
+
+/// The method a call was made to, as (destination, interface, member)
+type MethodKey = (Option<String>, Option<String>, Option<String>);
+
+/// Pairs method calls with their replies from a sequence of messages, either as records produced
+/// by `convert::message_to_record` or straight from a capture, and collects statistics about them
+#[derive(Debug, Default)]
+pub struct Analysis {
+    /// Calls that haven't been replied to yet, by (sender, serial)
+    pending: HashMap<(String, i64), PendingCall>,
+    /// Latencies and error counts of the replied calls, by method
+    methods: BTreeMap<MethodKey, MethodStats>,
+    /// Number of error replies by (error name, method)
+    errors: BTreeMap<(String, MethodKey), i64>,
+    /// Number of calls seen so far, to keep unanswered calls in order
+    call_count: usize,
+    /// Number of messages that couldn't be read, and were left out
+    skipped: i64,
+}
+
+#[derive(Debug)]
+struct PendingCall {
+    index: usize,
+    method: MethodKey,
+    timestamp: DateTime<FixedOffset>,
+    call: Call,
+}
+
+/// A call as it was given, only converted to a record if it's reported as unanswered
+#[derive(Debug)]
+enum Call {
+    Record(Value),
+    Message(Message),
+}
+
+/// The header fields of a message that the analysis looks at
+struct Header {
+    timestamp: DateTime<FixedOffset>,
+    r#type: String,
+    serial: Option<i64>,
+    reply_serial: Option<i64>,
+    no_reply: bool,
+    sender: Option<String>,
+    destination: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    error_name: Option<String>,
+}
+
+impl Header {
+    fn from_record(message: &Value) -> Result<Header, String> {
+        let record = message
+            .as_record()
+            .map_err(|_| format!("Expected a message record, got {}", message.get_type()))?;
+
+        let get_str = |key: &str| {
+            record
+                .get(key)
+                .and_then(|v| v.as_str().ok())
+                .map(String::from)
+        };
+        let get_int = |key: &str| record.get(key).and_then(|v| v.as_int().ok());
+        Ok(Header {
+            timestamp: record
+                .get("timestamp")
+                .and_then(|v| v.as_date().ok())
+                .ok_or("Message record is missing its timestamp")?,
+            r#type: get_str("type").ok_or("Message record is missing its type")?,
+            serial: get_int("serial"),
+            reply_serial: get_int("reply_serial"),
+            no_reply: record
+                .get("no_reply")
+                .and_then(|v| v.as_bool().ok())
+                .unwrap_or(false),
+            sender: get_str("sender"),
+            destination: get_str("destination"),
+            interface: get_str("interface"),
+            member: get_str("member"),
+            error_name: get_str("error_name"),
+        })
+    }
+
+    fn from_message(message: &Message, timestamp: DateTime<FixedOffset>) -> Header {
+        Header {
+            timestamp,
+            r#type: convert::message_type_name(message.msg_type()).into(),
+            serial: message.get_serial().map(i64::from),
+            reply_serial: message.get_reply_serial().map(i64::from),
+            no_reply: message.get_no_reply(),
+            sender: message.sender().map(|s| s.to_string()),
+            destination: message.destination().map(|s| s.to_string()),
+            interface: message.interface().map(|s| s.to_string()),
+            member: message.member().map(|s| s.to_string()),
+            error_name: convert::message_error_name(message),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct MethodStats {
+    /// In nanoseconds
+    latencies: Vec<i64>,
+    errors: i64,
+}
+
+impl Analysis {
+    /// Add the next message record to the analysis
+    pub fn add(&mut self, message: &Value) -> Result<(), String> {
+        let header = Header::from_record(message)?;
+        self.add_header(header, || Call::Record(message.clone()));
+        Ok(())
+    }
+
+    /// Add the next message from a capture to the analysis. Its body is only decoded if it's an
+    /// unanswered call
+    pub fn add_message(&mut self, message: Message, timestamp: DateTime<FixedOffset>) {
+        let header = Header::from_message(&message, timestamp);
+        self.add_header(header, || Call::Message(message));
+    }
+
+    /// Count a message that couldn't be read
+    pub fn skip(&mut self) {
+        self.skipped += 1;
+    }
+
+    fn add_header(&mut self, header: Header, call: impl FnOnce() -> Call) {
+        match &header.r#type[..] {
+            "method_call" => {
+                let (Some(sender), Some(serial)) = (header.sender, header.serial) else {
+                    // Can't be paired with a reply
+                    return;
+                };
+                if header.no_reply {
+                    return;
+                }
+                self.pending.insert(
+                    (sender, serial),
+                    PendingCall {
+                        index: self.call_count,
+                        method: (header.destination, header.interface, header.member),
+                        timestamp: header.timestamp,
+                        call: call(),
+                    },
+                );
+                self.call_count += 1;
+            }
+            r#type @ ("method_return" | "error") => {
+                let call = header
+                    .destination
+                    .zip(header.reply_serial)
+                    .and_then(|key| self.pending.remove(&key));
+
+                if let Some(call) = &call {
+                    let latency = (header.timestamp - call.timestamp)
+                        .num_nanoseconds()
+                        .unwrap_or(i64::MAX);
+                    let stats = self.methods.entry(call.method.clone()).or_default();
+                    stats.latencies.push(latency);
+                    if r#type == "error" {
+                        stats.errors += 1;
+                    }
+                }
+
+                if r#type == "error" {
+                    let error_name = header.error_name.unwrap_or_else(|| "(unknown)".into());
+                    let method = call.map(|call| call.method).unwrap_or_default();
+                    *self.errors.entry((error_name, method)).or_default() += 1;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Produce the final report, with tables for `latency`, `unanswered`, and `errors`, and the
+    /// number of `skipped` messages
+    pub fn into_value(self, span: Span) -> Value {
+        let string_or_nothing = |s: Option<String>| s.map(|s| Value::string(s, span));
+        let method_record = |(destination, interface, member): MethodKey| {
+            record! {
+                "destination" => string_or_nothing(destination).unwrap_or_default(),
+                "interface" => string_or_nothing(interface).unwrap_or_default(),
+                "member" => string_or_nothing(member).unwrap_or_default(),
+            }
+        };
+
+        let mut latency = self
+            .methods
+            .into_iter()
+            .map(|(method, mut stats)| {
+                stats.latencies.sort_unstable();
+                let total = stats
+                    .latencies
+                    .iter()
+                    .fold(0i64, |a, b| a.saturating_add(*b));
+                let count = stats.latencies.len();
+                (total, method, count, stats)
+            })
+            .collect::<Vec<_>>();
+        // Slowest overall first, since those are the most interesting
+        latency.sort_by(|a, b| b.0.cmp(&a.0));
+        let latency = latency
+            .into_iter()
+            .map(|(total, method, count, stats)| {
+                let mut record = method_record(method);
+                let duration = |nanos: i64| Value::duration(nanos, span);
+                record.insert("calls", Value::int(count as i64, span));
+                record.insert("errors", Value::int(stats.errors, span));
+                record.insert("min", duration(stats.latencies[0]));
+                record.insert("median", duration(stats.latencies[count / 2]));
+                record.insert("max", duration(stats.latencies[count - 1]));
+                record.insert("mean", duration(total / count as i64));
+                record.insert("total", duration(total));
+                Value::record(record, span)
+            })
+            .collect();
+
+        let mut unanswered = self.pending.into_values().collect::<Vec<_>>();
+        unanswered.sort_by_key(|call| call.index);
+        let unanswered = unanswered
+            .into_iter()
+            .map(|pending| match pending.call {
+                Call::Record(record) => record,
+                Call::Message(message) => {
+                    convert::message_to_record(&message, pending.timestamp, span)
+                        .unwrap_or_else(|err| Value::error(LabeledError::new(err).into(), span))
+                }
+            })
+            .collect();
+
+        let mut errors = self.errors.into_iter().collect::<Vec<_>>();
+        errors.sort_by(|a, b| b.1.cmp(&a.1));
+        let errors = errors
+            .into_iter()
+            .map(|((error_name, method), count)| {
+                let mut record = Record::new();
+                record.insert("error_name", Value::string(error_name, span));
+                record.extend(method_record(method));
+                record.insert("count", Value::int(count, span));
+                Value::record(record, span)
+            })
+            .collect();
+
+        Value::record(
+            record! {
+                "latency" => Value::list(latency, span),
+                "unanswered" => Value::list(unanswered, span),
+                "errors" => Value::list(errors, span),
+                "skipped" => Value::int(self.skipped, span),
+            },
+            span,
+        )
+    }
+}
+
+#[cfg(test)]
+fn test_message(fields: &[(&str, Value)], millis: i64) -> Value {
+    let timestamp = DateTime::from_timestamp_millis(millis)
+        .unwrap()
+        .fixed_offset();
+    let mut record = Record::new();
+    record.insert("timestamp", Value::test_date(timestamp));
+    for (key, value) in fields {
+        record.insert(*key, value.clone());
+    }
+    Value::test_record(record)
+}
+
+#[cfg(test)]
+fn test_call(sender: &str, serial: i64, member: &str, millis: i64) -> Value {
+    test_message(
+        &[
+            ("type", Value::test_string("method_call")),
+            ("serial", Value::test_int(serial)),
+            ("sender", Value::test_string(sender)),
+            ("destination", Value::test_string("org.example")),
+            ("interface", Value::test_string("org.example.Iface")),
+            ("member", Value::test_string(member)),
+        ],
+        millis,
+    )
+}
+
+#[cfg(test)]
+fn test_reply(destination: &str, reply_serial: i64, error: Option<&str>, millis: i64) -> Value {
+    let mut fields = vec![
+        (
+            "type",
+            Value::test_string(if error.is_some() {
+                "error"
+            } else {
+                "method_return"
+            }),
+        ),
+        ("reply_serial", Value::test_int(reply_serial)),
+        ("sender", Value::test_string(":1.1")),
+        ("destination", Value::test_string(destination)),
+    ];
+    if let Some(error) = error {
+        fields.push(("error_name", Value::test_string(error)));
+    }
+    test_message(&fields, millis)
+}
+
+#[cfg(test)]
+fn analyze(messages: &[Value]) -> Record {
+    let mut analysis = Analysis::default();
+    for message in messages {
+        analysis.add(message).unwrap();
+    }
+    analysis
+        .into_value(Span::test_data())
+        .into_record()
+        .unwrap()
+}
+
+#[test]
+fn test_latency() {
+    let report = analyze(&[
+        test_call(":1.2", 1, "Slow", 0),
+        test_call(":1.3", 1, "Fast", 10),
+        test_reply(":1.3", 1, None, 15),
+        test_reply(":1.2", 1, None, 100),
+        test_call(":1.2", 2, "Fast", 200),
+        test_reply(":1.2", 2, None, 203),
+    ]);
+    let latency = report.get("latency").unwrap().as_list().unwrap();
+    assert_eq!(2, latency.len());
+
+    let slow = latency[0].as_record().unwrap();
+    assert_eq!("Slow", slow.get("member").unwrap().as_str().unwrap());
+    assert_eq!(1, slow.get("calls").unwrap().as_int().unwrap());
+    assert_eq!(100_000_000, slow.get("max").unwrap().as_duration().unwrap());
+
+    let fast = latency[1].as_record().unwrap();
+    assert_eq!("Fast", fast.get("member").unwrap().as_str().unwrap());
+    assert_eq!(2, fast.get("calls").unwrap().as_int().unwrap());
+    assert_eq!(3_000_000, fast.get("min").unwrap().as_duration().unwrap());
+    assert_eq!(8_000_000, fast.get("total").unwrap().as_duration().unwrap());
+}
+
+#[test]
+fn test_unanswered() {
+    let mut no_reply = test_call(":1.2", 3, "Fire", 0);
+    if let Value::Record { val, .. } = &mut no_reply {
+        val.to_mut().insert("no_reply", Value::test_bool(true));
+    }
+    let report = analyze(&[
+        test_call(":1.2", 1, "Hang", 0),
+        test_call(":1.2", 2, "Ok", 0),
+        no_reply,
+        // Same serial, different sender
+        test_reply(":1.3", 1, None, 5),
+        test_reply(":1.2", 2, None, 5),
+        test_call(":1.4", 1, "AlsoHang", 10),
+    ]);
+    let unanswered = report.get("unanswered").unwrap().as_list().unwrap();
+    let members = unanswered
+        .iter()
+        .map(|call| {
+            call.get_data_by_key("member")
+                .unwrap()
+                .into_string()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["Hang", "AlsoHang"], members);
+}
+
+#[test]
+fn test_errors() {
+    let report = analyze(&[
+        test_call(":1.2", 1, "Broken", 0),
+        test_reply(":1.2", 1, Some("org.example.Error.Failed"), 1),
+        test_call(":1.2", 2, "Broken", 2),
+        test_reply(":1.2", 2, Some("org.example.Error.Failed"), 3),
+        test_reply(":1.5", 9, Some("org.example.Error.Other"), 4),
+    ]);
+    let errors = report.get("errors").unwrap().as_list().unwrap();
+    assert_eq!(2, errors.len());
+
+    let failed = errors[0].as_record().unwrap();
+    assert_eq!(
+        "org.example.Error.Failed",
+        failed.get("error_name").unwrap().as_str().unwrap()
+    );
+    assert_eq!("Broken", failed.get("member").unwrap().as_str().unwrap());
+    assert_eq!(2, failed.get("count").unwrap().as_int().unwrap());
+
+    let other = errors[1].as_record().unwrap();
+    assert!(other.get("member").unwrap().is_nothing());
+
+    let latency = report.get("latency").unwrap().as_list().unwrap();
+    let broken = latency[0].as_record().unwrap();
+    assert_eq!(2, broken.get("errors").unwrap().as_int().unwrap());
+}
+
+#[test]
+fn test_messages() {
+    let timestamp = |millis| {
+        DateTime::from_timestamp_millis(millis)
+            .unwrap()
+            .fixed_offset()
+    };
+    let call = |serial, member| {
+        let mut message =
+            Message::new_method_call(":1.2", "/org/example", "org.example.Iface", member)
+                .unwrap()
+                .append1("arg");
+        message.set_serial(serial);
+        // Only the bus can set the sender, so turn the destination header field into it
+        let mut data = vec![];
+        message
+            .marshal(|bytes| {
+                data.extend_from_slice(bytes);
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+        let field = (16..data.len())
+            .step_by(8)
+            .find(|i| data[*i..].starts_with(&[6, 1, b's', 0]))
+            .unwrap();
+        data[field] = 7;
+        Message::demarshal(&data).unwrap()
+    };
+    let mut reply = call(1, "Answered").method_return();
+    reply.set_destination(Some(":1.2".into()));
+
+    let mut analysis = Analysis::default();
+    analysis.add_message(call(1, "Answered"), timestamp(0));
+    analysis.add_message(call(2, "Hang"), timestamp(1));
+    analysis.skip();
+    analysis.add_message(reply, timestamp(4));
+    let report = analysis
+        .into_value(Span::test_data())
+        .into_record()
+        .unwrap();
+
+    let latency = report.get("latency").unwrap().as_list().unwrap();
+    let answered = latency[0].as_record().unwrap();
+    assert_eq!(
+        "Answered",
+        answered.get("member").unwrap().as_str().unwrap()
+    );
+    assert_eq!(
+        4_000_000,
+        answered.get("max").unwrap().as_duration().unwrap()
+    );
+
+    // Unanswered calls are reported with their bodies
+    let unanswered = report.get("unanswered").unwrap().as_list().unwrap();
+    assert_eq!(1, unanswered.len());
+    assert_eq!(
+        Value::test_list(vec![Value::test_string("arg")]),
+        unanswered[0].get_data_by_key("body").unwrap()
+    );
+    assert_eq!(1, report.get("skipped").unwrap().as_int().unwrap());
+}
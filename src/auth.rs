@@ -0,0 +1,624 @@
+use std::{
+    fs::{self, DirBuilder},
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    os::unix::{
+        fs::{DirBuilderExt, MetadataExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(target_os = "linux")]
+use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+use crate::address::{self, Transport, UnixSocket};
+
+/// A mechanism of the SASL handshake that D-Bus connections start with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mechanism {
+    /// Credentials of the socket, checked by the kernel
+    External,
+    /// No credentials at all
+    Anonymous,
+    /// A secret cookie from `~/.dbus-keyrings`, shared with the server through the filesystem
+    CookieSha1,
+}
+
+/// How to authenticate to a D-Bus server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Auth {
+    pub mechanism: Mechanism,
+    /// A uid for EXTERNAL, a user name for DBUS_COOKIE_SHA1, or trace information for ANONYMOUS
+    pub identity: Option<String>,
+    /// The home directory with the keyrings for DBUS_COOKIE_SHA1
+    pub home: Option<String>,
+}
+
+impl Mechanism {
+    pub fn parse(name: &str) -> Option<Mechanism> {
+        match &name.to_ascii_uppercase()[..] {
+            "EXTERNAL" => Some(Mechanism::External),
+            "ANONYMOUS" => Some(Mechanism::Anonymous),
+            "DBUS_COOKIE_SHA1" => Some(Mechanism::CookieSha1),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mechanism::External => "EXTERNAL",
+            Mechanism::Anonymous => "ANONYMOUS",
+            Mechanism::CookieSha1 => "DBUS_COOKIE_SHA1",
+        }
+    }
+}
+
+impl Auth {
+    /// Fill in the user name and home directory that DBUS_COOKIE_SHA1 needs from the caller's
+    /// environment, which the plugin doesn't share
+    pub fn resolve(&mut self, env: &dyn Fn(&str) -> Option<String>) {
+        if self.mechanism == Mechanism::CookieSha1 {
+            self.identity = self.identity.take().or_else(|| env("USER"));
+            self.home = env("HOME");
+        }
+    }
+}
+
+/// How often to check whether libdbus has connected to the relay
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A connection to a server, before it's handed over to libdbus
+enum Stream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+/// Authenticate to the server at `address`, and return the address of a local socket relaying to
+/// it.
+///
+/// libdbus can't be told which mechanism to use, so it connects to the relay instead, which
+/// accepts any mechanism
+pub fn connect(address: &str, auth: &Auth, timeout: Duration) -> Result<String, String> {
+    let dir = relay_dir()?;
+    let result = connect_in(&dir, address, auth, timeout);
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&dir);
+    }
+    result
+}
+
+fn connect_in(dir: &Path, address: &str, auth: &Auth, timeout: Duration) -> Result<String, String> {
+    let mut auth = auth.clone();
+    if auth.mechanism == Mechanism::External && auth.identity.is_none() {
+        // The directory was just created by this process, so it's owned by its uid
+        let metadata = fs::metadata(dir)
+            .map_err(|err| format!("Can't find the uid of this process: {err}"))?;
+        auth.identity = Some(metadata.uid().to_string());
+    }
+
+    let mut error = String::new();
+    for address in address::parse(address).map_err(|err| err.msg)? {
+        let result = open(&address.transport, timeout).and_then(|mut stream| {
+            let guid = authenticate(&mut stream, &auth)?;
+            // Once relayed, the connection is idle for as long as the client likes
+            stream.set_timeout(None).map_err(|err| err.to_string())?;
+            Ok((stream, guid))
+        });
+        match result {
+            Ok((stream, guid)) => return listen(dir, stream, guid, timeout),
+            // Try the next address, like libdbus does
+            Err(err) => error = err,
+        }
+    }
+    Err(error)
+}
+
+/// Do the client side of the handshake, up to and including BEGIN. Returns the GUID of the server
+fn authenticate(stream: &mut (impl Read + Write), auth: &Auth) -> Result<String, String> {
+    let name = auth.mechanism.name();
+    let initial_response = match auth.mechanism {
+        Mechanism::External | Mechanism::Anonymous => auth.identity.clone(),
+        Mechanism::CookieSha1 => Some(
+            auth.identity
+                .clone()
+                .ok_or("Can't find the user name to authenticate as, give it with --identity")?,
+        ),
+    };
+
+    stream.write_all(b"\0").map_err(|err| err.to_string())?;
+    match initial_response {
+        Some(response) => write_line(stream, &format!("AUTH {name} {}", hex(response.as_bytes()))),
+        None => write_line(stream, &format!("AUTH {name}")),
+    }?;
+    loop {
+        let line = read_line(stream)?;
+        let (command, arg) = line.split_once(' ').unwrap_or((&line, ""));
+        match command {
+            "OK" => {
+                write_line(stream, "BEGIN")?;
+                return Ok(arg.into());
+            }
+            "DATA" if auth.mechanism == Mechanism::CookieSha1 => {
+                let data = unhex(arg).ok_or("The server sent invalid DATA")?;
+                let home = auth
+                    .home
+                    .as_deref()
+                    .ok_or("Can't find the keyrings, HOME is not set")?;
+                let response = cookie_response(home, &String::from_utf8_lossy(&data))?;
+                write_line(stream, &format!("DATA {}", hex(response.as_bytes())))?;
+            }
+            "REJECTED" => {
+                return Err(format!(
+                    "The server doesn't accept {name} authentication, only: {}",
+                    arg.split(' ').collect::<Vec<_>>().join(", ")
+                ))
+            }
+            "ERROR" => return Err(format!("{name} authentication failed: {arg}")),
+            _ => return Err(format!("Unexpected reply to authentication: {line}")),
+        }
+    }
+}
+
+/// Answer the challenge of DBUS_COOKIE_SHA1, which is `<context> <cookie id> <server challenge>`
+fn cookie_response(home: &str, challenge: &str) -> Result<String, String> {
+    let mut parts = challenge.split(' ');
+    let (Some(context), Some(id), Some(server_challenge), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(format!("Invalid DBUS_COOKIE_SHA1 challenge: {challenge}"));
+    };
+    if context.is_empty() || context.contains(['/', '.']) {
+        return Err(format!("Invalid DBUS_COOKIE_SHA1 context: {context}"));
+    }
+    let path = format!("{home}/.dbus-keyrings/{context}");
+    let keyring =
+        fs::read_to_string(&path).map_err(|err| format!("Can't read the keyring {path}: {err}"))?;
+    let cookie = keyring
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.split(' ');
+            (fields.next() == Some(id)).then(|| fields.nth(1))?
+        })
+        .ok_or_else(|| format!("Cookie {id} is not in the keyring {path}"))?;
+
+    let mut random = [0; 16];
+    fs::File::open("/dev/urandom")
+        .and_then(|mut file| file.read_exact(&mut random))
+        .map_err(|err| err.to_string())?;
+    let client_challenge = hex(&random);
+    Ok(format!(
+        "{client_challenge} {}",
+        cookie_hash(server_challenge, &client_challenge, cookie)
+    ))
+}
+
+fn cookie_hash(server_challenge: &str, client_challenge: &str, cookie: &str) -> String {
+    hex(&sha1(
+        format!("{server_challenge}:{client_challenge}:{cookie}").as_bytes(),
+    ))
+}
+
+/// Connect to a server, without authenticating. Reading and writing time out until the timeout
+/// is removed again
+fn open(transport: &Transport, timeout: Duration) -> Result<Stream, String> {
+    let stream = match transport {
+        Transport::Unix(UnixSocket::Path(path)) => UnixStream::connect(path).map(Stream::Unix),
+        #[cfg(target_os = "linux")]
+        Transport::Unix(UnixSocket::Abstract(name)) => SocketAddr::from_abstract_name(name)
+            .and_then(|addr| UnixStream::connect_addr(&addr))
+            .map(Stream::Unix),
+        #[cfg(not(target_os = "linux"))]
+        Transport::Unix(UnixSocket::Abstract(_)) => {
+            return Err("Abstract sockets only exist on Linux".into())
+        }
+        Transport::Tcp(tcp) | Transport::NonceTcp { tcp, .. } => {
+            let host = tcp.host.as_deref().unwrap_or("localhost");
+            let port = tcp
+                .port
+                .ok_or("Can't connect to a tcp address without a port")?;
+            connect_tcp(host, port, timeout).map(Stream::Tcp)
+        }
+        _ => {
+            return Err(
+                "Only unix:path, unix:abstract, tcp and nonce-tcp addresses can be used \
+                with a chosen authentication mechanism"
+                    .into(),
+            )
+        }
+    };
+    let mut stream = stream.map_err(|err| err.to_string())?;
+    stream
+        .set_timeout(Some(timeout))
+        .map_err(|err| err.to_string())?;
+    if let Transport::NonceTcp {
+        noncefile: Some(noncefile),
+        ..
+    } = transport
+    {
+        let nonce =
+            fs::read(noncefile).map_err(|err| format!("Can't read nonce {noncefile}: {err}"))?;
+        stream.write_all(&nonce).map_err(|err| err.to_string())?;
+    }
+    Ok(stream)
+}
+
+/// Connect to the first address of `host` that accepts the connection before the timeout
+fn connect_tcp(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    let mut error = io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{host} has no addresses to connect to"),
+    );
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => error = err,
+        }
+    }
+    Err(error)
+}
+
+/// Create a private directory for the socket of the relay
+fn relay_dir() -> Result<PathBuf, String> {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "nu_plugin_dbus-{}-{}",
+        std::process::id(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .map_err(|err| format!("Can't create {}: {err}", dir.display()))?;
+    Ok(dir)
+}
+
+/// Listen on a socket in a private directory for libdbus to connect to, and relay the
+/// authenticated stream to it. If nothing connects before the timeout, the stream is closed
+fn listen(dir: &Path, stream: Stream, guid: String, timeout: Duration) -> Result<String, String> {
+    let path = dir.join("socket");
+    let listener = UnixListener::bind(&path)
+        .and_then(|listener| listener.set_nonblocking(true).map(|()| listener))
+        .map_err(|err| err.to_string())?;
+
+    let dir = dir.to_owned();
+    thread::spawn(move || {
+        let accepted = accept_until(&listener, Instant::now() + timeout);
+        // Nothing else may connect
+        let _ = fs::remove_dir_all(&dir);
+        if let Ok(mut local) = accepted {
+            if accept_local(&mut local, &guid).is_ok() {
+                relay(local, stream);
+            }
+        }
+    });
+    Ok(format!(
+        "unix:path={}",
+        address::escape(&path.to_string_lossy())
+    ))
+}
+
+/// Wait for a connection on a non-blocking listener, until the deadline
+fn accept_until(listener: &UnixListener, deadline: Instant) -> io::Result<UnixStream> {
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Do the server side of the handshake with libdbus, which has nothing left to prove
+fn accept_local(stream: &mut (impl Read + Write), guid: &str) -> Result<(), String> {
+    let mut nul = [0];
+    stream.read_exact(&mut nul).map_err(|err| err.to_string())?;
+    loop {
+        let line = read_line(stream)?;
+        match line.split(' ').next() {
+            Some("AUTH") => write_line(stream, &format!("OK {guid}"))?,
+            Some("BEGIN") => return Ok(()),
+            // File descriptors can't be relayed
+            _ => write_line(stream, "ERROR")?,
+        }
+    }
+}
+
+/// Copy everything between the two connections, until one of them is closed
+fn relay(local: UnixStream, remote: Stream) {
+    let (Ok(mut local_read), Ok(mut remote_write)) = (local.try_clone(), remote.try_clone()) else {
+        return;
+    };
+    thread::spawn(move || {
+        let _ = io::copy(&mut local_read, &mut remote_write);
+        let _ = remote_write.shutdown_write();
+    });
+    let (mut local_write, mut remote_read) = (local, remote);
+    let _ = io::copy(&mut remote_read, &mut local_write);
+    let _ = local_write.shutdown(Shutdown::Write);
+}
+
+fn write_line(stream: &mut impl Write, line: &str) -> Result<(), String> {
+    stream
+        .write_all(format!("{line}\r\n").as_bytes())
+        .map_err(|err| err.to_string())
+}
+
+/// Read a line of the handshake, one byte at a time so that nothing after it is consumed
+fn read_line(stream: &mut impl Read) -> Result<String, String> {
+    let mut line = vec![];
+    let mut byte = [0];
+    while !line.ends_with(b"\r\n") {
+        if line.len() > 16384 {
+            return Err("Authentication line is too long".into());
+        }
+        match stream.read(&mut byte) {
+            Ok(0) => return Err("The connection was closed during authentication".into()),
+            Ok(_) => line.push(byte[0]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Err("Timed out waiting for the server during authentication".into())
+            }
+            Err(err) => return Err(err.to_string()),
+        }
+    }
+    line.truncate(line.len() - 2);
+    String::from_utf8(line).map_err(|_| "Invalid UTF-8 during authentication".into())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    use std::fmt::Write as _;
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// SHA-1, which DBUS_COOKIE_SHA1 requires. It's not used for anything else
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().expect("chunks of 4"));
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, h) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Unix(stream) => stream.read(buf),
+            Stream::Tcp(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Unix(stream) => stream.write(buf),
+            Stream::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Unix(stream) => stream.flush(),
+            Stream::Tcp(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Stream {
+    fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+        }
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Unix(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)
+            }
+            Stream::Tcp(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)
+            }
+        }
+    }
+
+    fn shutdown_write(&self) -> io::Result<()> {
+        match self {
+            Stream::Unix(stream) => stream.shutdown(Shutdown::Write),
+            Stream::Tcp(stream) => stream.shutdown(Shutdown::Write),
+        }
+    }
+}
+
+/// Run a server in another thread that expects `expected` from the client and sends `replies`
+#[cfg(test)]
+fn test_server(
+    expected: &'static str,
+    replies: &'static str,
+) -> (UnixStream, thread::JoinHandle<()>) {
+    let (client, mut server) = UnixStream::pair().unwrap();
+    let handle = thread::spawn(move || {
+        server.write_all(replies.as_bytes()).unwrap();
+        let mut received = vec![0; expected.len()];
+        server.read_exact(&mut received).unwrap();
+        assert_eq!(expected, String::from_utf8_lossy(&received));
+    });
+    (client, handle)
+}
+
+#[test]
+fn test_authenticate() {
+    let guid = "0123456789abcdef0123456789abcdef";
+    let anonymous = Auth {
+        mechanism: Mechanism::Anonymous,
+        identity: None,
+        home: None,
+    };
+    let (mut client, server) = test_server(
+        "\0AUTH ANONYMOUS\r\nBEGIN\r\n",
+        "OK 0123456789abcdef0123456789abcdef\r\n",
+    );
+    assert_eq!(Ok(guid.into()), authenticate(&mut client, &anonymous));
+    server.join().unwrap();
+
+    // "1000" in hex
+    let external = Auth {
+        mechanism: Mechanism::External,
+        identity: Some("1000".into()),
+        home: None,
+    };
+    let (mut client, server) = test_server(
+        "\0AUTH EXTERNAL 31303030\r\n",
+        "REJECTED ANONYMOUS DBUS_COOKIE_SHA1\r\n",
+    );
+    assert_eq!(
+        Err("The server doesn't accept EXTERNAL authentication, only: \
+            ANONYMOUS, DBUS_COOKIE_SHA1"
+            .into()),
+        authenticate(&mut client, &external)
+    );
+    server.join().unwrap();
+}
+
+#[test]
+fn test_authenticate_timeout() {
+    let (client, _server) = UnixStream::pair().unwrap();
+    let mut client = Stream::Unix(client);
+    client.set_timeout(Some(Duration::from_millis(10))).unwrap();
+    let anonymous = Auth {
+        mechanism: Mechanism::Anonymous,
+        identity: None,
+        home: None,
+    };
+    // The server never replies
+    assert_eq!(
+        Err("Timed out waiting for the server during authentication".into()),
+        authenticate(&mut client, &anonymous)
+    );
+}
+
+#[test]
+fn test_accept_local() {
+    let (mut client, mut server) = UnixStream::pair().unwrap();
+    // What libdbus sends, followed by the first message
+    client
+        .write_all(b"\0AUTH EXTERNAL 30\r\nNEGOTIATE_UNIX_FD\r\nBEGIN\r\nl\x01\x00\x01")
+        .unwrap();
+    accept_local(&mut server, "abc").unwrap();
+    let mut rest = [0; 4];
+    server.read_exact(&mut rest).unwrap();
+    assert_eq!(b"l\x01\x00\x01", &rest);
+    let mut replies = [0; 15];
+    client.read_exact(&mut replies).unwrap();
+    assert_eq!(b"OK abc\r\nERROR\r\n", &replies);
+}
+
+#[test]
+fn test_listen_timeout() {
+    let (mut remote, server) = UnixStream::pair().unwrap();
+    let address = listen(
+        &relay_dir().unwrap(),
+        Stream::Unix(server),
+        "abc".into(),
+        Duration::from_millis(10),
+    )
+    .unwrap();
+    let path = address.strip_prefix("unix:path=").unwrap();
+    let dir = std::path::Path::new(path).parent().unwrap().to_owned();
+    assert!(dir.exists());
+    // Nothing connects, so the relay gives up and closes the stream to the server
+    assert_eq!(0, remote.read(&mut [0]).unwrap());
+    assert!(!dir.exists());
+}
+
+#[test]
+fn test_sha1() {
+    assert_eq!("da39a3ee5e6b4b0d3255bfef95601890afd80709", hex(&sha1(b"")));
+    assert_eq!(
+        "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+        hex(&sha1(
+            b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+        ))
+    );
+    assert_eq!(
+        hex(&sha1(b"server:client:cookie")),
+        cookie_hash("server", "client", "cookie")
+    );
+    assert_eq!(Some(b"1000".to_vec()), unhex("31303030"));
+    assert_eq!(None, unhex("313"));
+}
@@ -0,0 +1,274 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use dbus::MessageType;
+use nu_protocol::{record, LabeledError, Span, Spanned, Value};
+
+use crate::{
+    client::DbusClient,
+    config::{DbusBusChoice, DbusClientConfig},
+    introspection::Node,
+    match_rule::MatchRule,
+};
+
+/// Introspection data of objects, so that calls don't have to introspect the object every time
+#[derive(Default)]
+pub struct IntrospectionCache {
+    buses: Mutex<HashMap<DbusBusChoice, BusCache>>,
+}
+
+/// The cache for one bus
+struct BusCache {
+    /// The address the watcher is connected to
+    address: String,
+    /// A connection subscribed to `NameOwnerChanged`, to find out when entries are stale
+    watcher: DbusClient,
+    entries: CacheEntries,
+}
+
+/// The cached data for one bus, without the connection
+#[derive(Debug, Default)]
+struct CacheEntries {
+    /// The unique name that owns each well-known name that was looked up
+    owners: HashMap<String, String>,
+    /// Introspection data by (unique owner, object path)
+    nodes: HashMap<(String, String), CachedNode>,
+}
+
+#[derive(Debug)]
+struct CachedNode {
+    node: Node,
+    fetched: Instant,
+}
+
+impl IntrospectionCache {
+    /// Introspect an object, reusing the data from an earlier call if it's recent enough and the
+    /// owner of `dest` hasn't changed since
+    pub fn introspect(
+        &self,
+        dbus: &DbusClient,
+        dest: &Spanned<String>,
+        object: &Spanned<String>,
+    ) -> Result<Node, LabeledError> {
+        let config = dbus.config();
+        // Without a bus, there's no way to find out when the other side changes
+        if config.cache_ttl.is_zero() || matches!(config.bus_choice.item, DbusBusChoice::Peer(_)) {
+            return dbus.introspect(dest, object);
+        }
+
+        // The lock is only held to look at the entries, not while waiting for the bus, so that a
+        // slow service doesn't hold up calls to others
+        let address = config.resolved_address()?.address;
+        let known_owner = match self.with_bus(config, &address, |bus| bus.entries.owner(&dest.item))
+        {
+            Some(owner) => owner,
+            None => {
+                let bus = BusCache::watch(config)?;
+                let mut buses = self.buses.lock().unwrap_or_else(|err| err.into_inner());
+                buses.entry(config.bus_choice.item.clone()).or_insert(bus);
+                None
+            }
+        };
+        // A name without an owner may be activatable, so then nothing is cached, and the
+        // introspection starts the service
+        let owner = known_owner
+            .clone()
+            .or_else(|| dbus.get_name_owner(dest).ok());
+        if let Some(node) = owner.as_ref().and_then(|owner| {
+            self.with_bus(config, &address, |bus| {
+                bus.entries
+                    .get(owner, &object.item, config.cache_ttl, Instant::now())
+                    .cloned()
+            })?
+        }) {
+            return Ok(node);
+        }
+
+        let node = dbus.introspect(dest, object)?;
+        if let Some(owner) = owner.or_else(|| dbus.get_name_owner(dest).ok()) {
+            self.with_bus(config, &address, |bus| {
+                if known_owner.is_none() {
+                    bus.entries.owners.insert(dest.item.clone(), owner.clone());
+                }
+                bus.entries
+                    .insert(owner, object.item.clone(), node.clone(), Instant::now());
+            });
+        }
+        Ok(node)
+    }
+
+    /// Use the cache of the configured bus, if it's watched and up to date
+    fn with_bus<T>(
+        &self,
+        config: &DbusClientConfig,
+        address: &str,
+        f: impl FnOnce(&mut BusCache) -> T,
+    ) -> Option<T> {
+        let mut buses = self.buses.lock().unwrap_or_else(|err| err.into_inner());
+        let bus = buses.get_mut(&config.bus_choice.item)?;
+        if bus.address != address || bus.update().is_err() {
+            // The environment points to another bus now, or changes may have been missed while
+            // the watcher was disconnected
+            buses.remove(&config.bus_choice.item);
+            return None;
+        }
+        Some(f(bus))
+    }
+
+    /// List the cached objects, as records
+    pub fn to_value(&self, span: Span) -> Value {
+        let buses = self.buses.lock().unwrap_or_else(|err| err.into_inner());
+        let now = Instant::now();
+        let mut rows = vec![];
+        for (bus_choice, bus) in buses.iter() {
+            let (kind, address) = bus_choice.kind_and_address();
+            let mut nodes = bus.entries.nodes.iter().collect::<Vec<_>>();
+            nodes.sort_by(|a, b| a.0.cmp(b.0));
+            for ((owner, object), cached) in nodes {
+                let mut names = bus
+                    .entries
+                    .owners
+                    .iter()
+                    .filter(|(name, name_owner)| *name_owner == owner && *name != owner)
+                    .map(|(name, _)| name.clone())
+                    .collect::<Vec<_>>();
+                names.sort();
+                let age = now.saturating_duration_since(cached.fetched).as_nanos();
+                rows.push(Value::record(
+                    record! {
+                        "bus" => Value::string(kind, span),
+                        "address" => address
+                            .map(|address| Value::string(address, span))
+                            .unwrap_or_default(),
+                        "owner" => Value::string(owner, span),
+                        "names" => Value::list(
+                            names.into_iter().map(|name| Value::string(name, span)).collect(),
+                            span,
+                        ),
+                        "object" => Value::string(object, span),
+                        "age" => Value::duration(age.try_into().unwrap_or(i64::MAX), span),
+                    },
+                    span,
+                ));
+            }
+        }
+        Value::list(rows, span)
+    }
+
+    /// Forget everything, returning the number of objects that were cached
+    pub fn clear(&self) -> usize {
+        let mut buses = self.buses.lock().unwrap_or_else(|err| err.into_inner());
+        buses.drain().map(|(_, bus)| bus.entries.nodes.len()).sum()
+    }
+}
+
+impl BusCache {
+    /// Start watching for changes of name owners on the bus
+    fn watch(config: &DbusClientConfig) -> Result<BusCache, LabeledError> {
+        let watcher = DbusClient::new(config.clone())?;
+        watcher.add_match(&MatchRule {
+            r#type: Some(MessageType::Signal),
+            sender: Some("org.freedesktop.DBus".into()),
+            path: Some("/org/freedesktop/DBus".into()),
+            interface: Some("org.freedesktop.DBus".into()),
+            member: Some("NameOwnerChanged".into()),
+            ..MatchRule::default()
+        })?;
+        Ok(BusCache {
+            address: config.resolved_address()?.address,
+            watcher,
+            entries: CacheEntries::default(),
+        })
+    }
+
+    /// Forget the entries that are stale, according to the signals received since last time
+    fn update(&mut self) -> Result<(), LabeledError> {
+        for message in self.watcher.pending_messages()? {
+            if let Ok((name, old_owner)) = message.read2::<&str, &str>() {
+                self.entries.name_owner_changed(name, old_owner);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CacheEntries {
+    /// The unique name owning `name`, if known
+    fn owner(&self, name: &str) -> Option<String> {
+        if name.starts_with(':') {
+            Some(name.into())
+        } else {
+            self.owners.get(name).cloned()
+        }
+    }
+
+    fn get(&self, owner: &str, object: &str, ttl: Duration, now: Instant) -> Option<&Node> {
+        self.nodes
+            .get(&(owner.into(), object.into()))
+            .filter(|cached| now.saturating_duration_since(cached.fetched) < ttl)
+            .map(|cached| &cached.node)
+    }
+
+    fn insert(&mut self, owner: String, object: String, node: Node, now: Instant) {
+        self.nodes
+            .insert((owner, object), CachedNode { node, fetched: now });
+    }
+
+    fn name_owner_changed(&mut self, name: &str, old_owner: &str) {
+        self.owners.remove(name);
+        if !old_owner.is_empty() {
+            self.nodes.retain(|(owner, _), _| owner != old_owner);
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_node(name: &str) -> Node {
+    Node {
+        name: Some(name.into()),
+        ..Node::default()
+    }
+}
+
+#[test]
+fn test_ttl() {
+    let mut entries = CacheEntries::default();
+    let start = Instant::now();
+    let ttl = Duration::from_secs(10);
+    entries.insert(":1.5".into(), "/a".into(), test_node("a"), start);
+
+    assert_eq!(Some(&test_node("a")), entries.get(":1.5", "/a", ttl, start));
+    assert_eq!(None, entries.get(":1.5", "/b", ttl, start));
+    assert_eq!(None, entries.get(":1.6", "/a", ttl, start));
+    assert_eq!(
+        None,
+        entries.get(":1.5", "/a", ttl, start + Duration::from_secs(11))
+    );
+}
+
+#[test]
+fn test_name_owner_changed() {
+    let mut entries = CacheEntries::default();
+    let now = Instant::now();
+    let ttl = Duration::from_secs(10);
+    entries.owners.insert("com.example".into(), ":1.5".into());
+    entries.insert(":1.5".into(), "/a".into(), test_node("a"), now);
+    entries.insert(":1.6".into(), "/a".into(), test_node("b"), now);
+
+    assert_eq!(Some(":1.5".into()), entries.owner("com.example"));
+    assert_eq!(Some(":1.7".into()), entries.owner(":1.7"));
+    assert_eq!(None, entries.owner("com.example.Other"));
+
+    // The name moves to another connection
+    entries.name_owner_changed("com.example", ":1.5");
+    assert_eq!(None, entries.owner("com.example"));
+    assert_eq!(None, entries.get(":1.5", "/a", ttl, now));
+    assert_eq!(Some(&test_node("b")), entries.get(":1.6", "/a", ttl, now));
+
+    // A name appearing doesn't make anything stale
+    entries.name_owner_changed("com.example.New", "");
+    assert_eq!(Some(&test_node("b")), entries.get(":1.6", "/a", ttl, now));
+}
@@ -1,24 +1,54 @@
-use dbus::{
-    arg::messageitem::MessageItem,
-    channel::{BusType, Channel},
-    Message,
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use nu_protocol::{LabeledError, Spanned, Value};
+
+use chrono::{DateTime, FixedOffset};
+use dbus::{arg::messageitem::MessageItem, channel::Channel, Message};
+use nu_protocol::{LabeledError, Signals, Spanned, Value};
 
 use crate::{
+    cache::IntrospectionCache,
     config::{DbusBusChoice, DbusClientConfig},
     convert::to_message_item,
     dbus_type::DbusType,
     introspection::Node,
+    match_rule::MatchRule,
     pattern::Pattern,
 };
 
 /// Executes D-Bus actions on a connection, handling nushell types
 pub struct DbusClient {
     config: DbusClientConfig,
-    conn: Channel,
+    conn: Arc<Channel>,
+    cache: Option<Arc<IntrospectionCache>>,
+}
+
+/// A match rule added with [`DbusClient::subscribe`], which is removed from the bus when this is
+/// dropped, so that a connection from `dbus connect` stops receiving the messages
+#[must_use]
+pub struct Subscription {
+    conn: Arc<Channel>,
+    rule: String,
+}
+
+/// Messages received by a client, from [`DbusClient::receive`]
+///
+/// An error is produced as the last item if the connection fails
+pub struct MessageStream<K> {
+    client: DbusClient,
+    /// Released when the stream is dropped
+    _keep: K,
+    signals: Signals,
+    /// Messages read from the connection, with the time they were read
+    received: VecDeque<(DateTime<FixedOffset>, Message)>,
+    failed: bool,
 }
 
+/// How often to check for interruption while waiting for incoming messages
+const RECEIVE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 // Convenience macros for error handling
 macro_rules! validate_with {
     ($type:ty, $spanned:expr) => {
@@ -29,29 +59,72 @@ macro_rules! validate_with {
 
 impl DbusClient {
     pub fn new(config: DbusClientConfig) -> Result<DbusClient, LabeledError> {
-        // Try to connect to the correct D-Bus destination, as specified in the config
-        let channel = match &config.bus_choice.item {
-            DbusBusChoice::Session => Channel::get_private(BusType::Session),
-            DbusBusChoice::System => Channel::get_private(BusType::System),
-            DbusBusChoice::Started => Channel::get_private(BusType::Starter),
-            DbusBusChoice::Peer(address) => Channel::open_private(address),
-            DbusBusChoice::Bus(address) => Channel::open_private(address).and_then(|mut ch| {
-                ch.register()?;
-                Ok(ch)
-            }),
+        let channel = DbusClient::connect(&config)?;
+        Ok(DbusClient::with_channel(config, Arc::new(channel)))
+    }
+
+    /// Use an existing connection, which may be shared with other clients
+    pub fn with_channel(config: DbusClientConfig, channel: Arc<Channel>) -> DbusClient {
+        DbusClient {
+            config,
+            conn: channel,
+            cache: None,
         }
-        .map_err(|err| {
+    }
+
+    /// Reuse introspection data from the cache when determining signatures
+    pub fn with_cache(mut self, cache: Arc<IntrospectionCache>) -> DbusClient {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Open a new private connection to the configured bus
+    pub fn connect(config: &DbusClientConfig) -> Result<Channel, LabeledError> {
+        // Addresses given with --bus and --peer are already validated. Ones from the environment
+        // are left to libdbus, which knows more transports, like autolaunch: and launchd:
+        let address = config.resolved_address()?;
+        let local_address;
+        let open_address = match &config.auth {
+            Some(auth) => {
+                local_address =
+                    crate::auth::connect(&address.address, &auth.item, config.timeout().item)
+                        .map_err(|err| {
+                            LabeledError::new(err).with_label(
+                                format!(
+                                    "while authenticating with {} to D-Bus at {address}",
+                                    auth.item.mechanism.name()
+                                ),
+                                auth.span,
+                            )
+                        })?;
+                &local_address
+            }
+            None => &address.address,
+        };
+        let channel = Channel::open_private(open_address).and_then(|mut ch| {
+            // Peers aren't buses, so they don't know Hello
+            if !matches!(config.bus_choice.item, DbusBusChoice::Peer(_)) {
+                ch.register()?;
+            }
+            Ok(ch)
+        });
+        channel.map_err(|err| {
             LabeledError::new(err.to_string()).with_label(
-                "while connecting to D-Bus as specified here",
+                format!("while connecting to D-Bus at {address}"),
                 config.bus_choice.span,
             )
-        })?;
-        Ok(DbusClient {
-            config,
-            conn: channel,
         })
     }
 
+    /// The connection used by this client
+    pub fn channel(&self) -> &Arc<Channel> {
+        &self.conn
+    }
+
+    pub fn config(&self) -> &DbusClientConfig {
+        &self.config
+    }
+
     fn error(&self, err: impl std::fmt::Display, msg: impl std::fmt::Display) -> LabeledError {
         LabeledError::new(err.to_string()).with_label(msg.to_string(), self.config.span)
     }
@@ -78,7 +151,7 @@ impl DbusClient {
         // Send and get the response
         let resp = self
             .conn
-            .send_with_reply_and_block(message, self.config.timeout.item)
+            .send_with_reply_and_block(message, self.config.timeout().item)
             .map_err(|err| self.error(err, context))?;
 
         // Parse it to a Node
@@ -89,6 +162,18 @@ impl DbusClient {
         Node::from_xml(xml).map_err(|err| self.error(err, context))
     }
 
+    /// Introspect a D-Bus object, using the cache if there is one
+    fn introspect_cached(
+        &self,
+        dest: &Spanned<String>,
+        object: &Spanned<String>,
+    ) -> Result<Node, LabeledError> {
+        match &self.cache {
+            Some(cache) => cache.introspect(self, dest, object),
+            None => self.introspect(dest, object),
+        }
+    }
+
     /// Try to use introspection to get the signature of a method
     fn get_method_signature_by_introspection(
         &self,
@@ -97,7 +182,7 @@ impl DbusClient {
         interface: &Spanned<String>,
         method: &Spanned<String>,
     ) -> Result<Vec<DbusType>, LabeledError> {
-        let node = self.introspect(dest, object)?;
+        let node = self.introspect_cached(dest, object)?;
 
         if let Some(sig) = node.get_method_args_signature(&interface.item, &method.item) {
             DbusType::parse_all(&sig).map_err(|err| {
@@ -127,7 +212,7 @@ impl DbusClient {
         interface: &Spanned<String>,
         property: &Spanned<String>,
     ) -> Result<Vec<DbusType>, LabeledError> {
-        let node = self.introspect(dest, object)?;
+        let node = self.introspect_cached(dest, object)?;
 
         if let Some(sig) = node.get_property_signature(&interface.item, &property.item) {
             DbusType::parse_all(sig).map_err(|err| {
@@ -170,7 +255,11 @@ impl DbusClient {
         let valid_interface = validate_with!(dbus::strings::Interface, interface)?;
         let valid_method = validate_with!(dbus::strings::Member, method)?;
 
-        // Parse the signature
+        // Parse the signature, or the one configured for the method
+        let signature = signature.or_else(|| {
+            self.config
+                .signature_override(&dest.item, &interface.item, &method.item)
+        });
         let mut valid_signature = signature
             .map(|s| {
                 DbusType::parse_all(&s.item).map_err(|err| {
@@ -223,13 +312,68 @@ impl DbusClient {
         // Send it on the channel and get the response
         let resp = self
             .conn
-            .send_with_reply_and_block(message, self.config.timeout.item)
+            .send_with_reply_and_block(message, self.config.timeout().item)
             .map_err(|err| self.error(err, context))?;
 
         crate::convert::from_message(&resp, self.config.span)
             .map_err(|err| self.error(err, context))
     }
 
+    /// Emit a signal from the given object, to everyone listening or only to `dest`
+    pub fn emit(
+        &self,
+        dest: Option<&Spanned<String>>,
+        object: &Spanned<String>,
+        interface: &Spanned<String>,
+        signal: &Spanned<String>,
+        signature: Option<&Spanned<String>>,
+        args: &[Value],
+    ) -> Result<(), LabeledError> {
+        let context = "while emitting a D-Bus signal";
+
+        // Validate inputs before sending to the dbus lib so we don't panic
+        let valid_dest = dest
+            .map(|dest| validate_with!(dbus::strings::BusName, dest))
+            .transpose()?;
+        let valid_object = validate_with!(dbus::strings::Path, object)?;
+        let valid_interface = validate_with!(dbus::strings::Interface, interface)?;
+        let valid_signal = validate_with!(dbus::strings::Member, signal)?;
+
+        let valid_signature = signature
+            .map(|s| {
+                DbusType::parse_all(&s.item).map_err(|err| {
+                    LabeledError::new(err).with_label("in signature specified here", s.span)
+                })
+            })
+            .transpose()?;
+
+        if let Some(sig) = &valid_signature {
+            if sig.len() != args.len() {
+                return Err(self.error(
+                    format!("expected {} arguments, got {}", sig.len(), args.len()),
+                    context,
+                ));
+            }
+        }
+
+        let mut message = Message::new_signal(&*valid_object, &*valid_interface, &*valid_signal)
+            .map_err(|err| self.error(err, context))?;
+        if let Some(dest) = valid_dest {
+            message.set_destination(Some(dest));
+        }
+
+        let sigs_iter = valid_signature
+            .iter()
+            .flatten()
+            .map(Some)
+            .chain(std::iter::repeat(None));
+        for (val, sig) in args.iter().zip(sigs_iter) {
+            message = message.append1(to_message_item(val, sig)?);
+        }
+
+        self.send(message)
+    }
+
     /// Get a D-Bus property from the given object
     pub fn get(
         &self,
@@ -306,7 +450,11 @@ impl DbusClient {
         let valid_dest = validate_with!(dbus::strings::BusName, dest)?;
         let valid_object = validate_with!(dbus::strings::Path, object)?;
 
-        // Parse the signature
+        // Parse the signature, or the one configured for the property
+        let signature = signature.or_else(|| {
+            self.config
+                .signature_override(&dest.item, &interface.item, &property.item)
+        });
         let mut valid_signature = signature
             .map(|s| {
                 DbusType::parse_all(&s.item).map_err(|err| {
@@ -363,25 +511,38 @@ impl DbusClient {
 
         // Send it on the channel and get the response
         self.conn
-            .send_with_reply_and_block(message, self.config.timeout.item)
+            .send_with_reply_and_block(message, self.config.timeout().item)
             .map_err(|err| self.error(err, context))?;
 
         Ok(())
     }
 
     pub fn list(&self, pattern: Option<&Pattern>) -> Result<Vec<String>, LabeledError> {
+        self.list_by("ListNames", pattern)
+    }
+
+    /// List the names that the bus can start a service for, whether or not it's running
+    pub fn list_activatable(&self, pattern: Option<&Pattern>) -> Result<Vec<String>, LabeledError> {
+        self.list_by("ListActivatableNames", pattern)
+    }
+
+    fn list_by(
+        &self,
+        method: &str,
+        pattern: Option<&Pattern>,
+    ) -> Result<Vec<String>, LabeledError> {
         let context = "while listing D-Bus connection names";
 
         let message = Message::new_method_call(
             "org.freedesktop.DBus",
             "/org/freedesktop/DBus",
             "org.freedesktop.DBus",
-            "ListNames",
+            method,
         )
         .map_err(|err| self.error(err, context))?;
 
         self.conn
-            .send_with_reply_and_block(message, self.config.timeout.item)
+            .send_with_reply_and_block(message, self.config.timeout().item)
             .map_err(|err| self.error(err, context))
             .and_then(|reply| reply.read1().map_err(|err| self.error(err, context)))
             .map(|names: Vec<String>| {
@@ -396,4 +557,499 @@ impl DbusClient {
                 }
             })
     }
+
+    /// Ask the bus to give this connection a well-known name
+    ///
+    /// Returns the reply code from the bus, e.g. 1 if we became the primary owner
+    pub fn request_name(&self, name: &Spanned<String>, flags: u32) -> Result<u32, LabeledError> {
+        let context = "while requesting a D-Bus name";
+        validate_with!(dbus::strings::BusName, name)?;
+
+        let message = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "RequestName",
+        )
+        .map_err(|err| self.error(err, context))?
+        .append2(&name.item, flags);
+
+        self.conn
+            .send_with_reply_and_block(message, self.config.timeout().item)
+            .map_err(|err| self.error(err, context))
+            .and_then(|reply| reply.read1().map_err(|err| self.error(err, context)))
+    }
+
+    /// Get the unique name of the connection that owns a name
+    pub fn get_name_owner(&self, name: &Spanned<String>) -> Result<String, LabeledError> {
+        let context = "while looking up the owner of a D-Bus name";
+        validate_with!(dbus::strings::BusName, name)?;
+
+        let message = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "GetNameOwner",
+        )
+        .map_err(|err| self.error(err, context))?
+        .append1(&name.item);
+
+        self.conn
+            .send_with_reply_and_block(message, self.config.timeout().item)
+            .map_err(|err| self.error(err, context))
+            .and_then(|reply| reply.read1().map_err(|err| self.error(err, context)))
+    }
+
+    /// Ask the bus to start the service that owns a name, if it isn't running yet
+    ///
+    /// Returns the reply code from the bus, e.g. 2 if it was already running
+    pub fn start_service_by_name(&self, name: &Spanned<String>) -> Result<u32, LabeledError> {
+        let context = "while starting a D-Bus service";
+        validate_with!(dbus::strings::BusName, name)?;
+
+        let message = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "StartServiceByName",
+        )
+        .map_err(|err| self.error(err, context))?
+        .append2(&name.item, 0u32);
+
+        self.conn
+            .send_with_reply_and_block(message, self.config.timeout().item)
+            .map_err(|err| self.error(err, context))
+            .and_then(|reply| reply.read1().map_err(|err| self.error(err, context)))
+    }
+
+    /// Add or change environment variables of the services the bus starts from now on
+    pub fn update_activation_environment(
+        &self,
+        environment: &HashMap<String, String>,
+    ) -> Result<(), LabeledError> {
+        let context = "while updating the activation environment";
+
+        let message = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "UpdateActivationEnvironment",
+        )
+        .map_err(|err| self.error(err, context))?
+        .append1(environment);
+
+        self.conn
+            .send_with_reply_and_block(message, self.config.timeout().item)
+            .map_err(|err| self.error(err, context))
+            .map(|_| ())
+    }
+
+    /// List the connections that own or are waiting to own a name, the primary owner first
+    pub fn list_queued_owners(&self, name: &Spanned<String>) -> Result<Vec<String>, LabeledError> {
+        let context = "while listing the queued owners of a D-Bus name";
+        validate_with!(dbus::strings::BusName, name)?;
+
+        let message = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "ListQueuedOwners",
+        )
+        .map_err(|err| self.error(err, context))?
+        .append1(&name.item);
+
+        self.conn
+            .send_with_reply_and_block(message, self.config.timeout().item)
+            .map_err(|err| self.error(err, context))
+            .and_then(|reply| reply.read1().map_err(|err| self.error(err, context)))
+    }
+
+    /// Get what the bus knows about the process owning a name, e.g. `ProcessID`, as a record
+    pub fn get_connection_credentials(
+        &self,
+        name: &Spanned<String>,
+    ) -> Result<Value, LabeledError> {
+        let spanned = |item: &str| Spanned {
+            item: item.into(),
+            span: self.config.span,
+        };
+        self.call(
+            &spanned("org.freedesktop.DBus"),
+            &spanned("/org/freedesktop/DBus"),
+            &spanned("org.freedesktop.DBus"),
+            &spanned("GetConnectionCredentials"),
+            Some(&spanned("s")),
+            &[Value::string(&name.item, name.span)],
+        )
+        .map(|values| values.into_iter().next().unwrap_or_default())
+    }
+
+    /// Get the statistics of the bus daemon, as a record
+    pub fn get_stats(&self) -> Result<Value, LabeledError> {
+        let reply = self.call_stats("GetStats", None)?;
+        crate::convert::from_message(&reply, self.config.span)
+            .map(|values| values.into_iter().next().unwrap_or_default())
+            .map_err(|err| self.error(err, "while getting the statistics of the bus"))
+    }
+
+    /// Get the statistics of the connection owning a name, as a record
+    pub fn get_connection_stats(&self, name: &Spanned<String>) -> Result<Value, LabeledError> {
+        validate_with!(dbus::strings::BusName, name)?;
+        let reply = self.call_stats("GetConnectionStats", Some(name))?;
+        crate::convert::from_message(&reply, self.config.span)
+            .map(|values| values.into_iter().next().unwrap_or_default())
+            .map_err(|err| self.error(err, "while getting the statistics of a connection"))
+    }
+
+    /// Get the match rules of every connection, by unique name, or `None` if the bus provides
+    /// statistics but not this method, which was added to dbus-daemon later
+    pub fn get_all_match_rules(
+        &self,
+    ) -> Result<Option<HashMap<String, Vec<String>>>, LabeledError> {
+        let context = "while getting the match rules of the bus";
+
+        let message = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus.Debug.Stats",
+            "GetAllMatchRules",
+        )
+        .map_err(|err| self.error(err, context))?;
+
+        match self
+            .conn
+            .send_with_reply_and_block(message, self.config.timeout().item)
+        {
+            Ok(reply) => reply
+                .read1()
+                .map(Some)
+                .map_err(|err| self.error(err, context)),
+            Err(err) if err.name() == Some("org.freedesktop.DBus.Error.UnknownMethod") => Ok(None),
+            Err(err) => Err(self.error(err, context)),
+        }
+    }
+
+    /// Call a method of `org.freedesktop.DBus.Debug.Stats`, which only some buses have
+    fn call_stats(
+        &self,
+        method: &str,
+        name: Option<&Spanned<String>>,
+    ) -> Result<Message, LabeledError> {
+        let context = "while getting statistics from the bus";
+
+        let mut message = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus.Debug.Stats",
+            method,
+        )
+        .map_err(|err| self.error(err, context))?;
+        if let Some(name) = name {
+            message = message.append1(&name.item);
+        }
+
+        self.conn
+            .send_with_reply_and_block(message, self.config.timeout().item)
+            .map_err(|err| match err.name() {
+                Some(
+                    "org.freedesktop.DBus.Error.UnknownInterface"
+                    | "org.freedesktop.DBus.Error.UnknownMethod",
+                ) => self.error(err, context).with_help(
+                    "the bus doesn't provide statistics, \
+                        dbus-daemon has to be built with --enable-stats",
+                ),
+                _ => self.error(err, context),
+            })
+    }
+
+    /// Get the ID of the bus, which is different each time the bus is started
+    pub fn get_id(&self) -> Result<String, LabeledError> {
+        let context = "while getting the ID of the bus";
+
+        let message = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "GetId",
+        )
+        .map_err(|err| self.error(err, context))?;
+
+        self.conn
+            .send_with_reply_and_block(message, self.config.timeout().item)
+            .map_err(|err| self.error(err, context))
+            .and_then(|reply| reply.read1().map_err(|err| self.error(err, context)))
+    }
+
+    /// Get the ID of the machine that `dest` runs on, or the peer if `dest` is `None`
+    pub fn get_machine_id(&self, dest: Option<&str>) -> Result<String, LabeledError> {
+        let context = "while getting the machine ID";
+
+        let mut message = Message::new_method_call(
+            dest.unwrap_or("org.freedesktop.DBus"),
+            "/",
+            "org.freedesktop.DBus.Peer",
+            "GetMachineId",
+        )
+        .map_err(|err| self.error(err, context))?;
+        if dest.is_none() {
+            message.set_destination(None);
+        }
+
+        self.conn
+            .send_with_reply_and_block(message, self.config.timeout().item)
+            .map_err(|err| self.error(err, context))
+            .and_then(|reply| reply.read1().map_err(|err| self.error(err, context)))
+    }
+
+    /// Get a property of the bus itself, or `None` if the bus doesn't have it
+    pub fn get_bus_property(&self, property: &str) -> Result<Option<Value>, LabeledError> {
+        let context = "while getting a property of the bus";
+
+        let message = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus.Properties",
+            "Get",
+        )
+        .map_err(|err| self.error(err, context))?
+        .append2("org.freedesktop.DBus", property);
+
+        match self
+            .conn
+            .send_with_reply_and_block(message, self.config.timeout().item)
+        {
+            Ok(reply) => crate::convert::from_message(&reply, self.config.span)
+                .map(|values| values.into_iter().next())
+                .map_err(|err| self.error(err, context)),
+            Err(err)
+                if matches!(
+                    err.name(),
+                    Some(
+                        "org.freedesktop.DBus.Error.UnknownProperty"
+                            | "org.freedesktop.DBus.Error.InvalidArgs"
+                    )
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(self.error(err, context)),
+        }
+    }
+
+    /// Give up ownership of a well-known name, or leave the queue for it
+    ///
+    /// Returns the reply code from the bus, e.g. 1 if the name was released
+    pub fn release_name(&self, name: &Spanned<String>) -> Result<u32, LabeledError> {
+        let context = "while releasing a D-Bus name";
+        validate_with!(dbus::strings::BusName, name)?;
+
+        let message = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "ReleaseName",
+        )
+        .map_err(|err| self.error(err, context))?
+        .append1(&name.item);
+
+        self.conn
+            .send_with_reply_and_block(message, self.config.timeout().item)
+            .map_err(|err| self.error(err, context))
+            .and_then(|reply| reply.read1().map_err(|err| self.error(err, context)))
+    }
+
+    /// Send a message without waiting for a reply
+    pub fn send(&self, message: Message) -> Result<(), LabeledError> {
+        self.conn
+            .send(message)
+            .map_err(|()| self.error("Failed to send the message", "while sending to D-Bus"))?;
+        // Make sure it's sent even if the connection is closed right after
+        self.conn.flush();
+        Ok(())
+    }
+
+    /// Turn the connection into a monitor, which receives all messages matching the given rules
+    /// (or all messages, if there are none) but can no longer send messages
+    pub fn become_monitor(&self, rules: &[MatchRule]) -> Result<(), LabeledError> {
+        let context = "while becoming a D-Bus monitor";
+
+        let message = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus.Monitoring",
+            "BecomeMonitor",
+        )
+        .map_err(|err| self.error(err, context))?
+        .append2(
+            rules
+                .iter()
+                .map(|rule| rule.to_string())
+                .collect::<Vec<_>>(),
+            0u32,
+        );
+
+        self.conn
+            .send_with_reply_and_block(message, self.config.timeout().item)
+            .map_err(|err| self.error(err, context))?;
+
+        Ok(())
+    }
+
+    /// Ask the bus to send us messages matching the given rule
+    pub fn add_match(&self, rule: &MatchRule) -> Result<(), LabeledError> {
+        let context = "while adding a D-Bus match rule";
+
+        let message = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "AddMatch",
+        )
+        .map_err(|err| self.error(err, context))?
+        .append1(rule.to_string());
+
+        self.conn
+            .send_with_reply_and_block(message, self.config.timeout().item)
+            .map_err(|err| self.error(err, context))?;
+
+        Ok(())
+    }
+
+    /// Add a match rule for as long as the returned subscription is kept. Messages that arrived
+    /// before are dropped, so that ones left over on a shared connection aren't mistaken for new
+    pub fn subscribe(&self, rule: &MatchRule) -> Result<Subscription, LabeledError> {
+        self.pending_messages()?;
+        self.add_match(rule)?;
+        Ok(Subscription {
+            conn: self.conn.clone(),
+            rule: rule.to_string(),
+        })
+    }
+
+    /// Wait for the first message matching the rule, until the timeout expires
+    pub fn wait_for(&self, rule: &MatchRule, signals: &Signals) -> Result<Message, LabeledError> {
+        let context = "while waiting for a D-Bus message";
+        let deadline = Instant::now() + self.config.timeout().item;
+
+        loop {
+            signals.check(self.config.span)?;
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(
+                    LabeledError::new("Timed out waiting for a matching message")
+                        .with_label("waited for this long", self.config.timeout().span),
+                );
+            }
+
+            let message = self
+                .conn
+                .blocking_pop_message(remaining.min(RECEIVE_POLL_INTERVAL))
+                .map_err(|err| self.error(err, context))?;
+
+            if let Some(message) = message.filter(|m| rule.matches(m)) {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Take the messages that have already arrived, without waiting
+    pub fn pending_messages(&self) -> Result<Vec<Message>, LabeledError> {
+        self.conn.read_write(Some(Duration::ZERO)).map_err(|()| {
+            self.error(
+                "The connection was closed",
+                "while receiving D-Bus messages",
+            )
+        })?;
+        Ok(std::iter::from_fn(|| self.conn.pop_message()).collect())
+    }
+
+    /// Receive messages from the connection until it is closed or the user interrupts, keeping
+    /// `keep` (like a [`Subscription`]) for as long as the stream is
+    pub fn receive<K>(self, keep: K, signals: Signals) -> MessageStream<K> {
+        MessageStream {
+            client: self,
+            _keep: keep,
+            signals,
+            received: VecDeque::new(),
+            failed: false,
+        }
+    }
+}
+
+impl<K> MessageStream<K> {
+    /// The client the messages are received with, to reply or make calls with
+    pub fn client(&self) -> &DbusClient {
+        &self.client
+    }
+
+    /// Wait for the next incoming message, along with the time it was read from the connection
+    ///
+    /// Returns `None` if the user interrupts, or an error if the connection fails
+    pub fn next_timestamped(
+        &mut self,
+    ) -> Option<Result<(DateTime<FixedOffset>, Message), LabeledError>> {
+        while !self.failed {
+            if let Some(received) = self.received.pop_front() {
+                return Some(Ok(received));
+            }
+            if self.signals.interrupted() {
+                return None;
+            }
+            // Messages may already have been read while making a call on the client
+            if !self.pop_received() {
+                if self
+                    .client
+                    .conn
+                    .read_write(Some(RECEIVE_POLL_INTERVAL))
+                    .is_err()
+                {
+                    self.failed = true;
+                    return Some(Err(self.client.error(
+                        "The connection was closed",
+                        "while receiving D-Bus messages",
+                    )));
+                }
+                self.pop_received();
+            }
+        }
+        None
+    }
+
+    /// Take all of the messages that have been read, returning whether there were any
+    fn pop_received(&mut self) -> bool {
+        let timestamp = chrono::Local::now().fixed_offset();
+        let before = self.received.len();
+        self.received
+            .extend(std::iter::from_fn(|| self.client.conn.pop_message()).map(|m| (timestamp, m)));
+        self.received.len() > before
+    }
+}
+
+impl<K> Iterator for MessageStream<K> {
+    type Item = Result<Message, LabeledError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_timestamped()
+            .map(|result| result.map(|(_, message)| message))
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let Ok(message) = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "RemoveMatch",
+        ) else {
+            return;
+        };
+        let mut message = message.append1(&self.rule);
+        // Don't wait for the reply, as streams are dropped on the thread that reads them
+        message.set_no_reply(true);
+        if self.conn.send(message).is_ok() {
+            self.conn.flush();
+        }
+    }
 }
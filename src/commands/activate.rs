@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
+use nu_protocol::{Example, LabeledError, Signature, Spanned, SyntaxShape, Type, Value};
+
+use crate::{config::DbusClientConfig, DbusSignatureUtilExt};
+
+pub struct Activate;
+
+/// How long to wait for the service to start if neither --timeout nor the plugin config set one,
+/// the same as libdbus
+const DEFAULT_ACTIVATION_TIMEOUT: Duration = Duration::from_secs(25);
+
+impl SimplePluginCommand for Activate {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus activate"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .accepts_timeout()
+            .input_output_type(Type::Nothing, Type::String)
+            .required(
+                "name",
+                SyntaxShape::String,
+                "The well-known name of the service to start",
+            )
+    }
+
+    fn description(&self) -> &str {
+        "Start the service that owns a name, if it isn't running yet"
+    }
+
+    fn extra_description(&self) -> &str {
+        "The bus starts the service described by its .service file for the name, and replies \
+            once the service owns the name. Services can take a while to start, so unless a \
+            timeout is set with --timeout or the plugin config, this waits for up to 25 seconds.
+
+Returns `started` or `already_running`."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["dbus", "activate", "start", "service", "launch"]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![Example {
+            example: "dbus activate --timeout 30sec org.freedesktop.Notifications",
+            description: "Start the notification daemon",
+            result: None,
+        }]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let mut config = DbusClientConfig::new(engine, call)?;
+        config.timeout.get_or_insert(Spanned {
+            item: DEFAULT_ACTIVATION_TIMEOUT,
+            span: call.head,
+        });
+        let dbus = plugin.connections.get_or_connect(engine, config)?;
+        let name: Spanned<String> = call.req(0)?;
+
+        let outcome = match dbus.start_service_by_name(&name)? {
+            1 => "started",
+            2 => "already_running",
+            other => {
+                return Err(LabeledError::new(format!(
+                    "Unexpected reply {other} from StartServiceByName"
+                ))
+                .with_label("while starting this service", name.span))
+            }
+        };
+        Ok(Value::string(outcome, call.head))
+    }
+}
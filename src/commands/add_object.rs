@@ -0,0 +1,81 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
+use nu_protocol::{Example, LabeledError, Signature, Spanned, SyntaxShape, Type, Value};
+
+use crate::{client::DbusClient, config::DbusClientConfig, DbusSignatureUtilExt};
+
+pub struct AddObject;
+
+impl SimplePluginCommand for AddObject {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus add-object"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .input_output_type(Type::Nothing, Type::Nothing)
+            .required(
+                "object",
+                SyntaxShape::String,
+                "The path to export the object at",
+            )
+            .required(
+                "interfaces",
+                SyntaxShape::Record(vec![]),
+                "The interfaces of the object, described like in `dbus serve`",
+            )
+    }
+
+    fn description(&self) -> &str {
+        "Export another object while running `dbus serve`"
+    }
+
+    fn extra_description(&self) -> &str {
+        "If the object is below the path given to `dbus serve --object-manager`, \
+            InterfacesAdded is emitted."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["dbus", "serve", "export", "object", "add", "register"]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![Example {
+            example: "dbus add-object /com/example/Devices/1 {
+    com.example.Device: {
+        properties: { Name: { type: s, value: mouse } }
+    }
+}",
+            description: "Add a device to the objects served by `dbus serve`",
+            result: None,
+        }]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let config = DbusClientConfig::new(engine, call)?;
+        let server = plugin.servers.get(&config.bus_choice.item).ok_or_else(|| {
+            LabeledError::new("No objects are being served on this bus")
+                .with_label("run `dbus serve` first", call.head)
+        })?;
+        let object: Spanned<String> = call.req(0)?;
+
+        let signal = server
+            .tree
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .add_object(&object, &call.req(1)?)?;
+        if let Some(signal) = signal {
+            DbusClient::with_channel(config, server.channel).send(signal)?;
+        }
+        Ok(Value::nothing(call.head))
+    }
+}
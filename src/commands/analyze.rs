@@ -0,0 +1,121 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Example, LabeledError, PipelineData, Signature, Spanned, SyntaxShape, Type, Value,
+};
+
+use crate::{analysis::Analysis, pcap::PcapReader, DbusSignatureUtilExt};
+
+pub struct Analyze;
+
+impl PluginCommand for Analyze {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus analyze"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .input_output_types(vec![
+                (
+                    Type::List(Type::Record([].into()).into()),
+                    Type::Record([].into()),
+                ),
+                (Type::Nothing, Type::Record([].into())),
+            ])
+            .optional(
+                "file",
+                SyntaxShape::Filepath,
+                "A pcap file to analyze, instead of the messages from the input",
+            )
+    }
+
+    fn description(&self) -> &str {
+        "Pair method calls with their replies in captured traffic and summarize them"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Takes messages in the format produced by `dbus monitor` or `dbus read-capture`, or \
+            reads them from a pcap file. Calls are paired with replies by the caller's unique \
+            name and serial. The result has three tables: `latency` with the time taken to reply \
+            per destination, interface and member, slowest overall first; `unanswered` with the \
+            calls that never got a reply (except those that didn't expect one); and `errors` \
+            with the number of error replies by error name and the method that failed. \
+            Messages that can't be read, like errors from `dbus read-capture`, are left out and \
+            counted in `skipped`."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec![
+            "dbus", "analyze", "latency", "slow", "reply", "capture", "pcap", "profile",
+        ]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                example: "dbus analyze login.pcap | get latency | first 10",
+                description: "Find the methods that took the most time during a capture",
+                result: None,
+            },
+            Example {
+                example: "dbus monitor | take until { $in.member == Quit } | dbus analyze | \
+                    get unanswered",
+                description: "Find calls that never got a reply while monitoring",
+                result: None,
+            },
+        ]
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let mut analysis = Analysis::default();
+        let signals = engine.signals();
+
+        if let Some(path) = call.opt::<Spanned<PathBuf>>(0)? {
+            let full_path = Path::new(&engine.get_current_dir()?).join(&path.item);
+            let read_error = |err: String| {
+                LabeledError::new(format!("Failed to read {}", full_path.display()))
+                    .with_label(err, path.span)
+            };
+            let reader = File::open(&full_path)
+                .map_err(|err| err.to_string())
+                .and_then(|file| PcapReader::new(BufReader::new(file)))
+                .map_err(read_error)?;
+            for result in reader {
+                signals.check(call.head)?;
+                // Broken captures are what there is to analyze, so make the most of them
+                match result {
+                    Ok((timestamp, message)) => analysis.add_message(message, timestamp),
+                    Err(_) => analysis.skip(),
+                }
+            }
+        } else {
+            for value in input {
+                signals.check(call.head)?;
+                let span = value.span();
+                if let Value::Error { .. } = value {
+                    analysis.skip();
+                    continue;
+                }
+                analysis.add(&value).map_err(|err| {
+                    LabeledError::new(err).with_label("while analyzing this message", span)
+                })?;
+            }
+        }
+
+        Ok(PipelineData::Value(analysis.into_value(call.head), None))
+    }
+}
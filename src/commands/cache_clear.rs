@@ -0,0 +1,51 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
+use nu_protocol::{Example, LabeledError, Signature, Type, Value};
+
+use crate::DbusSignatureUtilExt;
+
+pub struct CacheClear;
+
+impl SimplePluginCommand for CacheClear {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus cache clear"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .input_output_type(Type::Nothing, Type::Int)
+    }
+
+    fn description(&self) -> &str {
+        "Forget the cached introspection data of all objects"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Returns the number of objects that were cached."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["dbus", "cache", "introspection", "clear", "reset"]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![Example {
+            example: "dbus cache clear",
+            description: "Make the next calls introspect their objects again",
+            result: None,
+        }]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        _engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let cleared = plugin.cache.clear();
+        Ok(Value::int(cleared as i64, call.head))
+    }
+}
@@ -0,0 +1,52 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
+use nu_protocol::{Example, LabeledError, Signature, Type, Value};
+
+use crate::DbusSignatureUtilExt;
+
+pub struct CacheList;
+
+impl SimplePluginCommand for CacheList {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus cache list"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .input_output_type(Type::Nothing, Type::table())
+    }
+
+    fn description(&self) -> &str {
+        "List the objects whose introspection data is cached"
+    }
+
+    fn extra_description(&self) -> &str {
+        "`dbus call` and `dbus set` introspect objects to determine the signature of their \
+            arguments, and keep the result for the time given by --cache-ttl. Entries are \
+            forgotten when the connection that owned the object leaves the bus."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["dbus", "cache", "introspection"]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![Example {
+            example: "dbus cache list | where object starts-with /org/mpris",
+            description: "Show the cached objects of media players",
+            result: None,
+        }]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        _engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        Ok(plugin.cache.to_value(call.head))
+    }
+}
@@ -1,7 +1,7 @@
 use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
 use nu_protocol::{Example, LabeledError, Signature, SyntaxShape, Type, Value};
 
-use crate::{client::DbusClient, config::DbusClientConfig, DbusSignatureUtilExt};
+use crate::{config::DbusClientConfig, DbusSignatureUtilExt};
 
 pub struct Call;
 
@@ -32,6 +32,13 @@ impl SimplePluginCommand for Call {
                 "Always return a list of all return values",
                 None,
             )
+            .named(
+                "cache-ttl",
+                SyntaxShape::Duration,
+                "How long to reuse introspection data for (default 1min). \
+                 Use 0sec to always introspect again",
+                None,
+            )
             .switch(
                 "no-introspect",
                 "Don't use introspection to determine the correct argument signature",
@@ -98,13 +105,16 @@ impl SimplePluginCommand for Call {
 
     fn run(
         &self,
-        _plugin: &Self::Plugin,
-        _engine: &EngineInterface,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
         call: &EvaluatedCall,
         _input: &Value,
     ) -> Result<Value, LabeledError> {
-        let config = DbusClientConfig::try_from(call)?;
-        let dbus = DbusClient::new(config)?;
+        let config = DbusClientConfig::new(engine, call)?;
+        let dbus = plugin
+            .connections
+            .get_or_connect(engine, config)?
+            .with_cache(plugin.cache.clone());
         let values = dbus.call(
             &call.get_flag("dest")?.unwrap(),
             &call.req(0)?,
@@ -0,0 +1,64 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
+use nu_protocol::{Example, LabeledError, Signature, Type, Value};
+
+use crate::{config::DbusClientConfig, DbusSignatureUtilExt};
+
+pub struct Connect;
+
+impl SimplePluginCommand for Connect {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus connect"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .input_output_type(Type::Nothing, Type::Custom("DbusConnection".into()))
+    }
+
+    fn description(&self) -> &str {
+        "Open a connection to use with the --connection flag of other commands"
+    }
+
+    fn extra_description(&self) -> &str {
+        "The connection keeps the same unique name for as long as it's open, for services that \
+            track their clients by name. It is closed when the value is dropped, or with \
+            `dbus disconnect --connection`."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["dbus", "connect", "connection", "open", "session", "unique"]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                example: "let conn = dbus connect --system; $conn.unique_name",
+                description: "Open a connection to the system bus, and get its unique name",
+                result: None,
+            },
+            Example {
+                example: "let conn = dbus connect
+dbus call --connection=$conn --dest=org.freedesktop.ScreenSaver /org/freedesktop/ScreenSaver \
+    org.freedesktop.ScreenSaver Inhibit nushell 'Running a long job'",
+                description: "Inhibit the screensaver until the connection is closed",
+                result: None,
+            },
+        ]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let config = DbusClientConfig::new(engine, call)?;
+        let handle = plugin.connections.connect(engine, &config)?;
+        Ok(handle.into_value(call.head))
+    }
+}
@@ -0,0 +1,77 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
+use nu_protocol::{Example, LabeledError, Signature, Type, Value};
+
+use crate::{config::DbusClientConfig, DbusSignatureUtilExt};
+
+pub struct Disconnect;
+
+impl SimplePluginCommand for Disconnect {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus disconnect"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .input_output_type(Type::Nothing, Type::Bool)
+            .switch("all", "Close the connections to all buses", None)
+    }
+
+    fn description(&self) -> &str {
+        "Close the connection the plugin keeps open to a bus"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Commands reuse a connection to each bus, so that they don't have to connect again every \
+            time. Closing it releases the names it owns, and the next command on the bus gets a \
+            new connection with a new unique name. Once all connections are closed, the plugin \
+            can be stopped when it's not in use.
+
+With --connection, the connection opened by `dbus connect` is closed instead. It's also closed \
+            when the value is dropped.
+
+Returns whether there was a connection to close."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["dbus", "disconnect", "close", "connection", "reset"]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                example: "dbus disconnect --system",
+                description: "Close the connection to the system bus",
+                result: None,
+            },
+            Example {
+                example: "dbus disconnect --all",
+                description: "Close all of the plugin's connections",
+                result: None,
+            },
+        ]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let config = DbusClientConfig::new(engine, call)?;
+        let closed = if let Some(id) = &config.connection {
+            plugin.connections.drop_handle(engine, id.item)?
+        } else if call.has_flag("all")? {
+            plugin.connections.disconnect(engine, None)?
+        } else {
+            plugin
+                .connections
+                .disconnect(engine, Some(&config.bus_choice.item))?
+        };
+        Ok(Value::bool(closed, call.head))
+    }
+}
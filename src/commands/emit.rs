@@ -0,0 +1,136 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
+use nu_protocol::{Example, LabeledError, Signature, Spanned, SyntaxShape, Type, Value};
+
+use crate::{client::DbusClient, config::DbusClientConfig, DbusSignatureUtilExt};
+
+pub struct Emit;
+
+impl SimplePluginCommand for Emit {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus emit"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .input_output_type(Type::Nothing, Type::Nothing)
+            .named(
+                "signature",
+                SyntaxShape::String,
+                "Signature of the arguments to send, in D-Bus format.\n    \
+                 If not provided, they will be taken from the objects exported by \
+                   `dbus serve`, or guessed (poorly) if the signal isn't declared there",
+                None,
+            )
+            .named(
+                "dest",
+                SyntaxShape::String,
+                "Send the signal only to this connection, instead of broadcasting it",
+                None,
+            )
+            .required(
+                "object",
+                SyntaxShape::String,
+                "The path to the object emitting the signal",
+            )
+            .required(
+                "interface",
+                SyntaxShape::String,
+                "The name of the interface the signal belongs to",
+            )
+            .required("signal", SyntaxShape::String, "The name of the signal")
+            .rest(
+                "args",
+                SyntaxShape::Any,
+                "Arguments to send with the signal",
+            )
+    }
+
+    fn description(&self) -> &str {
+        "Emit a signal"
+    }
+
+    fn extra_description(&self) -> &str {
+        "If objects are being exported with `dbus serve` on the same bus, the signal is sent on \
+            the same connection, so that it comes from the owner of the served names. Otherwise \
+            it is sent on the plugin's persistent connection to the bus."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["dbus", "signal", "emit", "send", "broadcast"]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                example: "dbus emit /com/example/Greeter com.example.Greeter Greeted nushell",
+                description: "Emit a signal declared by an object exported with `dbus serve`",
+                result: None,
+            },
+            Example {
+                example: "dbus emit --signature=su --dest=:1.42 \
+                    /com/example/Jobs com.example.Jobs Finished backup 0",
+                description: "Send a signal to a single connection",
+                result: None,
+            },
+        ]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let config = DbusClientConfig::new(engine, call)?;
+        let object: Spanned<String> = call.req(0)?;
+        let interface: Spanned<String> = call.req(1)?;
+        let signal: Spanned<String> = call.req(2)?;
+        let mut signature: Option<Spanned<String>> = call.get_flag("signature")?;
+
+        let server = plugin.servers.get(&config.bus_choice.item);
+        if signature.is_none() {
+            signature = server
+                .as_ref()
+                .and_then(|server| {
+                    server
+                        .tree
+                        .lock()
+                        .unwrap_or_else(|err| err.into_inner())
+                        .signal_signature(&object.item, &interface.item, &signal.item)
+                })
+                .map(|item| Spanned {
+                    item,
+                    span: signal.span,
+                });
+            if signature.is_none() {
+                eprintln!(
+                    "Warning: signal {:?} is not declared on an exported object. \
+                    Pass `--signature` to silence this warning.",
+                    signal.item
+                );
+            }
+        }
+
+        // Prefer the connection serving the objects, unless one was given
+        let dbus = match server {
+            Some(server) if config.connection.is_none() => {
+                DbusClient::with_channel(config, server.channel)
+            }
+            _ => plugin.connections.get_or_connect(engine, config)?,
+        };
+        dbus.emit(
+            call.get_flag("dest")?.as_ref(),
+            &object,
+            &interface,
+            &signal,
+            signature.as_ref(),
+            &call.positional[3..],
+        )?;
+        Ok(Value::nothing(call.head))
+    }
+}
@@ -1,7 +1,7 @@
 use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
 use nu_protocol::{Example, LabeledError, Signature, SyntaxShape, Type, Value};
 
-use crate::{client::DbusClient, config::DbusClientConfig, DbusSignatureUtilExt};
+use crate::{config::DbusClientConfig, DbusSignatureUtilExt};
 
 pub struct Get;
 
@@ -68,13 +68,13 @@ impl SimplePluginCommand for Get {
 
     fn run(
         &self,
-        _plugin: &Self::Plugin,
-        _engine: &EngineInterface,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
         call: &EvaluatedCall,
         _input: &Value,
     ) -> Result<Value, LabeledError> {
-        let config = DbusClientConfig::try_from(call)?;
-        let dbus = DbusClient::new(config)?;
+        let config = DbusClientConfig::new(engine, call)?;
+        let dbus = plugin.connections.get_or_connect(engine, config)?;
         dbus.get(
             &call.get_flag("dest")?.unwrap(),
             &call.req(0)?,
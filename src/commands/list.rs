@@ -1,5 +1,9 @@
+use std::collections::{BTreeSet, HashSet};
+
 use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
-use nu_protocol::{Example, LabeledError, Signature, SyntaxShape, Type, Value};
+use nu_protocol::{
+    record, Example, LabeledError, Signature, Span, Spanned, SyntaxShape, Type, Value,
+};
 
 use crate::{client::DbusClient, config::DbusClientConfig, pattern::Pattern, DbusSignatureUtilExt};
 
@@ -17,7 +21,16 @@ impl SimplePluginCommand for List {
             .dbus_command()
             .accepts_dbus_client_options()
             .accepts_timeout()
-            .input_output_type(Type::Nothing, Type::List(Type::String.into()))
+            .input_output_types(vec![
+                (Type::Nothing, Type::List(Type::String.into())),
+                (Type::Nothing, Type::table()),
+            ])
+            .switch(
+                "long",
+                "Return a table with the owner and process of each name, \
+                    including names that can be activated",
+                Some('l'),
+            )
             .optional(
                 "pattern",
                 SyntaxShape::String,
@@ -30,7 +43,12 @@ impl SimplePluginCommand for List {
     }
 
     fn extra_description(&self) -> &str {
-        "These can be used as arguments for --dest on any of the other commands."
+        "These can be used as arguments for --dest on any of the other commands.
+
+With --long, each row has the unique name owning the name, whether the bus can activate it, the \
+            pid and uid of the owner, its command line from /proc (if the bus is on this \
+            machine), and the connections queued to own the name after the owner. Names that \
+            can be activated but aren't running are included, with `running` set to false."
     }
 
     fn search_terms(&self) -> Vec<&str> {
@@ -55,6 +73,12 @@ impl SimplePluginCommand for List {
                     Value::test_string("org.freedesktop.Notifications"),
                 ])),
             },
+            Example {
+                example: "dbus list --long --system | where running",
+                description: "List the names on the system bus with the processes owning them, \
+                    like `busctl list`",
+                result: None,
+            },
             Example {
                 example: "dbus list org.mpris.MediaPlayer2.**",
                 description: "List all MPRIS2 media players on the bus",
@@ -68,16 +92,19 @@ impl SimplePluginCommand for List {
 
     fn run(
         &self,
-        _plugin: &Self::Plugin,
-        _engine: &EngineInterface,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
         call: &EvaluatedCall,
         _input: &Value,
     ) -> Result<Value, LabeledError> {
-        let config = DbusClientConfig::try_from(call)?;
-        let dbus = DbusClient::new(config)?;
+        let config = DbusClientConfig::new(engine, call)?;
+        let dbus = plugin.connections.get_or_connect(engine, config)?;
         let pattern = call
             .opt::<String>(0)?
             .map(|pat| Pattern::new(&pat, Some('.')));
+        if call.has_flag("long")? {
+            return list_long(&dbus, pattern.as_ref(), call.head);
+        }
         let result = dbus.list(pattern.as_ref())?;
         Ok(Value::list(
             result
@@ -88,3 +115,92 @@ impl SimplePluginCommand for List {
         ))
     }
 }
+
+/// Describe each name with its owner and process, like `busctl list`
+fn list_long(
+    dbus: &DbusClient,
+    pattern: Option<&Pattern>,
+    span: Span,
+) -> Result<Value, LabeledError> {
+    let running = dbus.list(pattern)?.into_iter().collect::<HashSet<_>>();
+    let activatable = dbus
+        .list_activatable(pattern)?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let names = running.union(&activatable).collect::<BTreeSet<_>>();
+    // The pids are of processes on the machine of the bus, which may not be this one
+    let local = dbus
+        .get_machine_id(Some("org.freedesktop.DBus"))
+        .is_ok_and(|machine_id| is_local_machine(&machine_id));
+
+    let spanned = |item: &str| Spanned {
+        item: item.to_owned(),
+        span,
+    };
+    let rows = names.into_iter().map(|name| {
+        let is_running = running.contains(name);
+        // Names can go away while the others are looked up, so errors just leave columns empty
+        let owner = is_running
+            .then(|| dbus.get_name_owner(&spanned(name)).ok())
+            .flatten();
+        let credentials = owner
+            .as_ref()
+            .and_then(|owner| dbus.get_connection_credentials(&spanned(owner)).ok());
+        let credential = |key: &str| {
+            credentials
+                .as_ref()
+                .and_then(|credentials| credentials.get_data_by_key(key))
+                .unwrap_or_default()
+        };
+        let pid = credential("ProcessID");
+        let cmdline = pid
+            .as_int()
+            .ok()
+            .filter(|_| local)
+            .and_then(|pid| std::fs::read(format!("/proc/{pid}/cmdline")).ok())
+            .map(|cmdline| {
+                let args = cmdline
+                    .split(|byte| *byte == 0)
+                    .filter(|arg| !arg.is_empty())
+                    .map(String::from_utf8_lossy)
+                    .collect::<Vec<_>>();
+                Value::string(args.join(" "), span)
+            })
+            .unwrap_or_default();
+        // Unique names can't be queued for
+        let queued = (is_running && !name.starts_with(':'))
+            .then(|| dbus.list_queued_owners(&spanned(name)).ok())
+            .flatten()
+            .map(|owners| {
+                let waiting = owners.into_iter().skip(1);
+                Value::list(
+                    waiting.map(|owner| Value::string(owner, span)).collect(),
+                    span,
+                )
+            })
+            .unwrap_or_default();
+
+        Value::record(
+            record! {
+                "name" => Value::string(name.as_str(), span),
+                "owner" => owner.map(|owner| Value::string(owner, span)).unwrap_or_default(),
+                "running" => Value::bool(is_running, span),
+                "activatable" => Value::bool(activatable.contains(name), span),
+                "pid" => pid,
+                "uid" => credential("UnixUserID"),
+                "cmdline" => cmdline,
+                "queued" => queued,
+            },
+            span,
+        )
+    });
+    Ok(Value::list(rows.collect(), span))
+}
+
+/// Whether the machine ID is the one of this machine
+fn is_local_machine(machine_id: &str) -> bool {
+    ["/etc/machine-id", "/var/lib/dbus/machine-id"]
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+        .is_some_and(|local| local.trim() == machine_id)
+}
@@ -0,0 +1,110 @@
+use dbus::Message;
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    record, Example, LabeledError, ListStream, PipelineData, Signature, Span, Type, Value,
+};
+
+use crate::{config::DbusClientConfig, convert, match_rule::MatchRule, DbusSignatureUtilExt};
+
+pub struct Listen;
+
+impl PluginCommand for Listen {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus listen"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .accepts_timeout()
+            .input_output_type(Type::Nothing, Type::List(Type::Record([].into()).into()))
+            .accepts_signal_match_options()
+    }
+
+    fn description(&self) -> &str {
+        "Listen for signals on the bus"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Returns a stream with one record per signal received, until interrupted."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["dbus", "signal", "subscribe", "match", "receive"]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                example: "dbus listen --interface=org.freedesktop.DBus --member=NameOwnerChanged",
+                description: "Listen for names appearing on and disappearing from the bus",
+                result: None,
+            },
+            Example {
+                example: "dbus listen --path=/org/mpris/MediaPlayer2 \
+                    --interface=org.freedesktop.DBus.Properties \
+                    --arg0=org.mpris.MediaPlayer2.Player",
+                description: "Listen for changes to the state of media players",
+                result: None,
+            },
+            Example {
+                example: "dbus listen --system --sender=org.freedesktop.login1 \
+                    --arg1=seat0",
+                description: "Listen for signals from logind about the first seat",
+                result: None,
+            },
+        ]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let config = DbusClientConfig::new(engine, call)?;
+        let dbus = plugin.connections.private(config)?;
+        let rule = MatchRule::from_signal_flags(call)?;
+        let subscription = dbus.subscribe(&rule)?;
+
+        let span = call.head;
+        let signals = dbus
+            .receive(subscription, engine.signals().clone())
+            .filter(move |result| result.as_ref().map_or(true, |m| rule.matches(m)))
+            .map(move |result| {
+                result
+                    .and_then(|message| {
+                        signal_to_value(&message, span).map_err(|err| {
+                            LabeledError::new(err).with_label("while decoding a signal", span)
+                        })
+                    })
+                    .unwrap_or_else(|err| Value::error(err.into(), span))
+            });
+
+        Ok(PipelineData::ListStream(
+            ListStream::new(signals, span, engine.signals().clone()),
+            None,
+        ))
+    }
+}
+
+/// Represent a signal message as a nushell record
+fn signal_to_value(message: &Message, span: Span) -> Result<Value, String> {
+    let string_or_nothing = |s: Option<&str>| s.map(|s| Value::string(s, span)).unwrap_or_default();
+
+    Ok(Value::record(
+        record! {
+            "sender" => string_or_nothing(message.sender().as_deref()),
+            "path" => string_or_nothing(message.path().as_deref()),
+            "interface" => string_or_nothing(message.interface().as_deref()),
+            "member" => string_or_nothing(message.member().as_deref()),
+            "signature" => Value::string(convert::message_signature(message), span),
+            "args" => Value::list(convert::from_message(message, span)?, span),
+        },
+        span,
+    ))
+}
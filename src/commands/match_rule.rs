@@ -0,0 +1,81 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
+use nu_protocol::{Example, LabeledError, Signature, Type, Value};
+
+use crate::{match_rule, DbusSignatureUtilExt};
+
+pub struct MatchRule;
+
+impl SimplePluginCommand for MatchRule {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus match-rule"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .input_output_types(vec![
+                (Type::Record([].into()), Type::String),
+                (Type::String, Type::Record([].into())),
+            ])
+    }
+
+    fn description(&self) -> &str {
+        "Convert between D-Bus match rule strings and records"
+    }
+
+    fn extra_description(&self) -> &str {
+        "A record is converted to a validated match rule string, and a match rule string is \
+            parsed into a record. The keys of the record are the same as the keys of the match \
+            rule, e.g. `type`, `sender`, `path_namespace`, `arg0`, `arg1path` or `arg0namespace`."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["dbus", "match", "rule", "filter", "parse"]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                example: "{type: signal, interface: org.freedesktop.DBus, arg0: \"it's\"} | \
+                    dbus match-rule",
+                description: "Make a match rule string from a record",
+                result: Some(Value::test_string(
+                    r"type='signal',interface='org.freedesktop.DBus',arg0='it'\''s'",
+                )),
+            },
+            Example {
+                example: "\"type='signal',path_namespace='/org/mpris'\" | dbus match-rule",
+                description: "Parse a match rule string into a record",
+                result: Some(Value::test_record(nu_protocol::record!(
+                    "type" => Value::test_string("signal"),
+                    "path_namespace" => Value::test_string("/org/mpris"),
+                ))),
+            },
+        ]
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        _engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: &Value,
+    ) -> Result<Value, LabeledError> {
+        match input {
+            Value::String { val, .. } => match_rule::MatchRule::parse(val)
+                .map(|rule| rule.to_value(call.head))
+                .map_err(|err| {
+                    LabeledError::new("Invalid match rule").with_label(err, input.span())
+                }),
+            Value::Record { .. } => match_rule::MatchRule::from_value(input)
+                .map(|rule| Value::string(rule.to_string(), call.head)),
+            _ => Err(LabeledError::new(format!(
+                "Expected a string or record, got {}",
+                input.get_type()
+            ))
+            .with_label("convert this to a match rule", input.span())),
+        }
+    }
+}
@@ -1,15 +1,61 @@
+mod activate;
+mod add_object;
+mod analyze;
+mod cache_clear;
+mod cache_list;
 mod call;
+mod connect;
+mod disconnect;
+mod emit;
 mod get;
 mod get_all;
 mod introspect;
 mod list;
+mod listen;
 mod main;
+mod match_rule;
+mod monitor;
+mod parse_address;
+mod read_capture;
+mod release_name;
+mod remove_object;
+mod request_name;
+mod serve;
 mod set;
+mod stats;
+mod update_activation_env;
+mod wait_signal;
+mod watch;
+mod watch_names;
+mod whoami;
 
+pub use activate::Activate;
+pub use add_object::AddObject;
+pub use analyze::Analyze;
+pub use cache_clear::CacheClear;
+pub use cache_list::CacheList;
 pub use call::Call;
+pub use connect::Connect;
+pub use disconnect::Disconnect;
+pub use emit::Emit;
 pub use get::Get;
 pub use get_all::GetAll;
 pub use introspect::Introspect;
 pub use list::List;
+pub use listen::Listen;
 pub use main::Main;
+pub use match_rule::MatchRule;
+pub use monitor::Monitor;
+pub use parse_address::ParseAddress;
+pub use read_capture::ReadCapture;
+pub use release_name::ReleaseName;
+pub use remove_object::RemoveObject;
+pub use request_name::RequestName;
+pub use serve::Serve;
 pub use set::Set;
+pub use stats::Stats;
+pub use update_activation_env::UpdateActivationEnv;
+pub use wait_signal::WaitSignal;
+pub use watch::Watch;
+pub use watch_names::WatchNames;
+pub use whoami::Whoami;
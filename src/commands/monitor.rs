@@ -0,0 +1,156 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Example, LabeledError, ListStream, PipelineData, Signature, Spanned, SyntaxShape, Type, Value,
+};
+
+use crate::{
+    client::DbusClient, config::DbusClientConfig, convert, match_rule::MatchRule, pcap::PcapWriter,
+    DbusSignatureUtilExt,
+};
+
+pub struct Monitor;
+
+impl PluginCommand for Monitor {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus monitor"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .accepts_timeout()
+            .input_output_type(Type::Nothing, Type::List(Type::Record([].into()).into()))
+            .named(
+                "pcap",
+                SyntaxShape::Filepath,
+                "Also write the monitored messages to a pcap file, like `busctl capture`",
+                None,
+            )
+            .rest(
+                "rules",
+                SyntaxShape::String,
+                "Match rules to filter the monitored messages by. \
+                 If none are given, all messages are monitored",
+            )
+    }
+
+    fn description(&self) -> &str {
+        "Monitor the messages flowing through the bus"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Returns a stream with one record per message, including its header fields and body. \
+            Monitoring usually requires the bus to be configured to allow it, which is the case \
+            for the session bus, but often not the system bus. With `--pcap`, the messages are \
+            also saved in a capture that can be opened in Wireshark or with `dbus read-capture`."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["dbus", "monitor", "sniff", "eavesdrop", "traffic"]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                example: "dbus monitor",
+                description: "Monitor all messages on the session bus",
+                result: None,
+            },
+            Example {
+                example: "dbus monitor \"type='signal',interface='org.freedesktop.DBus'\"",
+                description: "Monitor signals sent by the bus itself",
+                result: None,
+            },
+            Example {
+                example: "dbus monitor \"interface='org.freedesktop.Notifications'\" | \
+                    where type == method_call | get body",
+                description: "Watch the notifications being sent to the desktop",
+                result: None,
+            },
+            Example {
+                example: "dbus monitor --pcap bug.pcap | ignore",
+                description: "Capture all messages on the session bus to a file",
+                result: None,
+            },
+        ]
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let config = DbusClientConfig::new(engine, call)?;
+        // A monitor can't do anything else, so this would make the connection useless
+        if let Some(id) = &config.connection {
+            return Err(
+                LabeledError::new("Can't monitor on a connection from `dbus connect`")
+                    .with_label("use --session, --system, or --bus instead", id.span),
+            );
+        }
+        let dbus = DbusClient::new(config)?;
+        let rules = call
+            .rest::<Spanned<String>>(0)?
+            .into_iter()
+            .map(|rule| {
+                MatchRule::parse(&rule.item).map_err(|err| {
+                    LabeledError::new("Invalid match rule").with_label(err, rule.span)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut capture = call
+            .get_flag::<Spanned<PathBuf>>("pcap")?
+            .map(|path| create_capture(engine, path))
+            .transpose()?;
+        dbus.become_monitor(&rules)?;
+
+        let span = call.head;
+        let mut received = dbus.receive((), engine.signals().clone());
+        let messages = std::iter::from_fn(move || received.next_timestamped()).map(move |result| {
+            result
+                .and_then(|(timestamp, message)| {
+                    if let Some(capture) = &mut capture {
+                        capture.write_message(&message, &timestamp).map_err(|err| {
+                            LabeledError::new(err.to_string())
+                                .with_label("while writing to the capture", span)
+                        })?;
+                    }
+                    convert::message_to_record(&message, timestamp, span).map_err(|err| {
+                        LabeledError::new(err)
+                            .with_label("while decoding a monitored message", span)
+                    })
+                })
+                .unwrap_or_else(|err| Value::error(err.into(), span))
+        });
+
+        Ok(PipelineData::ListStream(
+            ListStream::new(messages, span, engine.signals().clone()),
+            None,
+        ))
+    }
+}
+
+/// Create the pcap file to write monitored messages to, relative to the current directory
+fn create_capture(
+    engine: &EngineInterface,
+    path: Spanned<PathBuf>,
+) -> Result<PcapWriter<BufWriter<File>>, LabeledError> {
+    let full_path = Path::new(&engine.get_current_dir()?).join(&path.item);
+    File::create(&full_path)
+        .and_then(|file| PcapWriter::new(BufWriter::new(file)))
+        .map_err(|err| {
+            LabeledError::new(format!("Failed to create {}", full_path.display()))
+                .with_label(err.to_string(), path.span)
+        })
+}
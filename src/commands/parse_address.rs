@@ -0,0 +1,92 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
+use nu_protocol::{record, Example, LabeledError, Signature, Spanned, SyntaxShape, Type, Value};
+
+use crate::{address, config::DbusClientConfig, DbusSignatureUtilExt};
+
+pub struct ParseAddress;
+
+impl SimplePluginCommand for ParseAddress {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus parse-address"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .input_output_type(Type::Nothing, Type::List(Type::record().into()))
+            .optional(
+                "address",
+                SyntaxShape::String,
+                "The address to parse (default: the address of the chosen bus)",
+            )
+    }
+
+    fn description(&self) -> &str {
+        "Parse a D-Bus server address"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Returns a record for each of the semicolon-separated addresses, with the transport and \
+            its keys, percent-escapes decoded. The addresses are tried in order when connecting."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["dbus", "address", "parse", "transport", "socket"]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                example:
+                    "dbus parse-address 'unix:path=/tmp/my%20bus;tcp:host=localhost,port=4000'",
+                description: "Parse an address with a fallback",
+                result: Some(Value::test_list(vec![
+                    Value::test_record(record!(
+                        "transport" => Value::test_string("unix"),
+                        "path" => Value::test_string("/tmp/my bus"),
+                    )),
+                    Value::test_record(record!(
+                        "transport" => Value::test_string("tcp"),
+                        "host" => Value::test_string("localhost"),
+                        "port" => Value::test_int(4000),
+                    )),
+                ])),
+            },
+            Example {
+                example: "dbus parse-address --system",
+                description: "Parse the address of the system bus",
+                result: None,
+            },
+        ]
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let addresses = match call.opt::<Spanned<String>>(0)? {
+            Some(address) => address::parse_spanned(&address.item, address.span)?,
+            None => {
+                let config = DbusClientConfig::new(engine, call)?;
+                let address = config.resolved_address()?;
+                address::parse(&address.address).map_err(|err| {
+                    LabeledError::new(err.msg)
+                        .with_label(format!("in the address {address}"), config.bus_choice.span)
+                })?
+            }
+        };
+        Ok(Value::list(
+            addresses
+                .iter()
+                .map(|address| address.to_value(call.head))
+                .collect(),
+            call.head,
+        ))
+    }
+}
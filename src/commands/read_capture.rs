@@ -0,0 +1,102 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Example, LabeledError, ListStream, PipelineData, Signature, Spanned, SyntaxShape, Type, Value,
+};
+
+use crate::{convert, pcap::PcapReader, DbusSignatureUtilExt};
+
+pub struct ReadCapture;
+
+impl PluginCommand for ReadCapture {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus read-capture"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .input_output_type(Type::Nothing, Type::List(Type::Record([].into()).into()))
+            .required(
+                "file",
+                SyntaxShape::Filepath,
+                "The pcap file containing the captured messages",
+            )
+    }
+
+    fn description(&self) -> &str {
+        "Read the messages from a D-Bus pcap capture"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Returns a stream with one record per captured message, in the same format as \
+            `dbus monitor`. Captures can be made with `dbus monitor --pcap` or `busctl capture`. \
+            The pcapng format is not supported."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["dbus", "pcap", "capture", "wireshark", "busctl", "offline"]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                example: "dbus read-capture bug.pcap | where type == error",
+                description: "Find the errors in a capture",
+                result: None,
+            },
+            Example {
+                example: "dbus read-capture bug.pcap | group-by sender | \
+                    transpose sender messages | update messages { length }",
+                description: "Count the messages sent by each connection",
+                result: None,
+            },
+        ]
+    }
+
+    fn run(
+        &self,
+        _plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let path: Spanned<PathBuf> = call.req(0)?;
+        let full_path = Path::new(&engine.get_current_dir()?).join(&path.item);
+        let reader = File::open(&full_path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| PcapReader::new(BufReader::new(file)))
+            .map_err(|err| {
+                LabeledError::new(format!("Failed to read {}", full_path.display()))
+                    .with_label(err, path.span)
+            })?;
+
+        let span = call.head;
+        let messages = reader.map(move |result| {
+            result
+                .and_then(|(timestamp, message)| {
+                    convert::message_to_record(&message, timestamp, span)
+                })
+                .unwrap_or_else(|err| {
+                    Value::error(
+                        LabeledError::new(err)
+                            .with_label("while reading this capture", path.span)
+                            .into(),
+                        span,
+                    )
+                })
+        });
+
+        Ok(PipelineData::ListStream(
+            ListStream::new(messages, span, engine.signals().clone()),
+            None,
+        ))
+    }
+}
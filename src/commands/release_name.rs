@@ -0,0 +1,73 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
+use nu_protocol::{Example, LabeledError, Signature, Spanned, SyntaxShape, Type, Value};
+
+use crate::{config::DbusClientConfig, DbusSignatureUtilExt};
+
+pub struct ReleaseName;
+
+impl SimplePluginCommand for ReleaseName {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus release-name"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .accepts_timeout()
+            .input_output_type(Type::Nothing, Type::String)
+            .required(
+                "name",
+                SyntaxShape::String,
+                "The well-known name to release",
+            )
+    }
+
+    fn description(&self) -> &str {
+        "Release a well-known name requested with `dbus request-name`"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Also removes the plugin's connection from the queue for the name, if it was waiting. \
+            Returns one of `released`, `non_existent` or `not_owner`."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["dbus", "name", "release", "disown"]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![Example {
+            example: "dbus release-name com.example.MyScript",
+            description: "Let another instance of a script take over",
+            result: None,
+        }]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let config = DbusClientConfig::new(engine, call)?;
+        let dbus = plugin.connections.get_or_connect(engine, config)?;
+        let name: Spanned<String> = call.req(0)?;
+
+        let outcome = match dbus.release_name(&name)? {
+            1 => "released",
+            2 => "non_existent",
+            3 => "not_owner",
+            other => {
+                return Err(
+                    LabeledError::new(format!("Unexpected reply {other} from ReleaseName"))
+                        .with_label("while releasing this name", name.span),
+                )
+            }
+        };
+        Ok(Value::string(outcome, call.head))
+    }
+}
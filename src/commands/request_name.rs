@@ -0,0 +1,100 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
+use nu_protocol::{Example, LabeledError, Signature, Spanned, SyntaxShape, Type, Value};
+
+use crate::{config::DbusClientConfig, DbusSignatureUtilExt};
+
+pub struct RequestName;
+
+impl SimplePluginCommand for RequestName {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus request-name"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .accepts_timeout()
+            .input_output_type(Type::Nothing, Type::String)
+            .switch(
+                "allow-replacement",
+                "Allow another connection to take the name with --replace-existing",
+                None,
+            )
+            .switch(
+                "replace-existing",
+                "Take the name from its current owner, if it allows replacement",
+                None,
+            )
+            .switch(
+                "do-not-queue",
+                "Don't wait in the queue for the name if it's already owned",
+                None,
+            )
+            .required("name", SyntaxShape::String, "The well-known name to own")
+    }
+
+    fn description(&self) -> &str {
+        "Request ownership of a well-known name on the bus"
+    }
+
+    fn extra_description(&self) -> &str {
+        "The name is requested on a connection kept open by the plugin, so it stays owned after \
+            the command finishes, until it is released. Objects served with `dbus serve` on the \
+            same bus are available under the name.
+
+Returns one of `primary_owner`, `in_queue`, `exists` (with --do-not-queue) or `already_owner`."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["dbus", "name", "own", "acquire", "single", "instance"]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![Example {
+            example: "if (dbus request-name --do-not-queue com.example.MyScript) != primary_owner \
+                { error make { msg: 'already running' } }",
+            description: "Make sure only one instance of a script runs at a time",
+            result: None,
+        }]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let config = DbusClientConfig::new(engine, call)?;
+        let dbus = plugin.connections.get_or_connect(engine, config)?;
+        let name: Spanned<String> = call.req(0)?;
+
+        let mut flags = 0;
+        for (flag, bit) in [
+            ("allow-replacement", 1),
+            ("replace-existing", 2),
+            ("do-not-queue", 4),
+        ] {
+            if call.has_flag(flag)? {
+                flags |= bit;
+            }
+        }
+
+        let outcome = match dbus.request_name(&name, flags)? {
+            1 => "primary_owner",
+            2 => "in_queue",
+            3 => "exists",
+            4 => "already_owner",
+            other => {
+                return Err(
+                    LabeledError::new(format!("Unexpected reply {other} from RequestName"))
+                        .with_label("while requesting this name", name.span),
+                )
+            }
+        };
+        Ok(Value::string(outcome, call.head))
+    }
+}
@@ -0,0 +1,248 @@
+use std::sync::{Arc, Mutex};
+
+use dbus::MessageType;
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    record, Example, LabeledError, ListStream, PipelineData, Signature, Spanned, SyntaxShape, Type,
+    Value,
+};
+
+use crate::{
+    client::DbusClient,
+    config::DbusClientConfig,
+    server::{Dispatch, ObjectTree, Server},
+    DbusSignatureUtilExt,
+};
+
+/// The `DBUS_NAME_FLAG_DO_NOT_QUEUE` flag for RequestName
+const DO_NOT_QUEUE: u32 = 4;
+
+pub struct Serve;
+
+impl PluginCommand for Serve {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus serve"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .accepts_timeout()
+            .input_output_type(Type::Nothing, Type::List(Type::Record([].into()).into()))
+            .named(
+                "name",
+                SyntaxShape::String,
+                "A well-known name to own while serving the objects",
+                None,
+            )
+            .named(
+                "object-manager",
+                SyntaxShape::String,
+                "Implement org.freedesktop.DBus.ObjectManager at this path, for the objects \
+                    below it",
+                None,
+            )
+            .required(
+                "objects",
+                SyntaxShape::Record(vec![]),
+                "The objects to serve, with their interfaces and methods",
+            )
+    }
+
+    fn description(&self) -> &str {
+        "Export objects on the bus, with methods implemented by closures"
+    }
+
+    fn extra_description(&self) -> &str {
+        "The objects are described by a record of object paths to records of interface names to \
+            interface descriptions. An interface has `methods`, a record of method names to \
+            records with the `in` and `out` arguments (a signature, or a record of argument \
+            names to types) and the `closure` to run. It can also have `properties`, a record \
+            of property names to records with the `type`, `access` (read, write or readwrite) \
+            and initial `value`, and `signals`, a record of signal names to their arguments.
+
+The closure is called with the arguments of the method call, and the sender, path, interface \
+            and member of the call as input. Its result is sent as the reply: a list if the \
+            method has more than one output argument. If the closure fails, an error is sent \
+            instead.
+
+Every object also implements org.freedesktop.DBus.Introspectable, generated from its \
+            description, and org.freedesktop.DBus.Properties. When a property is set, \
+            PropertiesChanged is emitted. Paths above the objects can be introspected to find \
+            them.
+
+With --object-manager, GetManagedObjects returns the objects below the given path, and \
+            InterfacesAdded and InterfacesRemoved are emitted when objects are added with \
+            `dbus add-object` or removed with `dbus remove-object` while serving.
+
+Returns a stream with one record per method call handled, until interrupted."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec![
+            "dbus", "serve", "export", "service", "mock", "server", "object",
+        ]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                example: "dbus serve --name=com.example.Greeter {
+    /com/example/Greeter: {
+        com.example.Greeter: {
+            methods: {
+                Greet: { in: {name: s}, out: {greeting: s}, closure: {|name| $'Hello, ($name)!' } }
+            }
+        }
+    }
+}",
+                description: "Serve a method that greets its caller",
+                result: None,
+            },
+            Example {
+                example: "dbus serve --name=org.freedesktop.Notifications {
+    /org/freedesktop/Notifications: {
+        org.freedesktop.Notifications: {
+            methods: {
+                Notify: {
+                    in: susssasa{sv}i
+                    out: u
+                    closure: {|app, id, icon, summary, body| print $'($summary): ($body)'; 1 }
+                }
+            }
+        }
+    }
+}",
+                description: "Print notifications instead of showing them",
+                result: None,
+            },
+            Example {
+                example: "dbus serve --name=com.example.Settings {
+    /com/example/Settings: {
+        com.example.Settings: {
+            properties: {
+                Theme: { type: s, access: readwrite, value: dark }
+                Version: { type: u, value: 3 }
+            }
+        }
+    }
+}",
+                description: "Serve a settings object with properties that clients can watch",
+                result: None,
+            },
+            Example {
+                example:
+                    "dbus serve --name=com.example.Devices --object-manager=/com/example/Devices {
+    /com/example/Devices/0: {
+        com.example.Device: {
+            properties: { Name: { type: s, value: keyboard } }
+        }
+    }
+}",
+                description: "Serve device objects that clients can find with GetManagedObjects",
+                result: None,
+            },
+        ]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let config = DbusClientConfig::new(engine, call)?;
+        // Serve on the persistent connection, which owns names from `dbus request-name`, if
+        // there is one
+        let bus = config.bus_choice.clone();
+        let dbus = match plugin.connections.get(config.clone())? {
+            Some(dbus) => dbus,
+            None => DbusClient::new(config)?,
+        };
+        let mut tree = ObjectTree::from_value(&call.req(0)?)?;
+        if let Some(root) = call.get_flag::<Spanned<String>>("object-manager")? {
+            tree.set_object_manager(&root)?;
+        }
+        let tree = Arc::new(Mutex::new(tree));
+        // Let `dbus emit` find the signals of the objects, and send them on this connection
+        let guard = plugin.servers.register(
+            &bus,
+            Server {
+                tree: tree.clone(),
+                channel: dbus.channel().clone(),
+            },
+        )?;
+
+        if let Some(name) = call.get_flag::<Spanned<String>>("name")? {
+            // Already owning it is fine, e.g. after `dbus request-name` on the same connection
+            if !matches!(dbus.request_name(&name, DO_NOT_QUEUE)?, 1 | 4) {
+                return Err(LabeledError::new("Name is already owned")
+                    .with_label("another connection owns this name", name.span));
+            }
+        }
+
+        let span = call.head;
+        let closure_engine = engine.clone();
+        // The objects are served for as long as the stream is running
+        let mut messages = dbus.receive(guard, engine.signals().clone());
+        let calls = std::iter::from_fn(move || {
+            loop {
+                let message = match messages.next()? {
+                    Ok(message) => message,
+                    Err(err) => return Some(Value::error(err.into(), span)),
+                };
+                if message.msg_type() != MessageType::MethodCall {
+                    continue;
+                }
+
+                // Don't hold the lock while the closure runs, so it can use `dbus emit`
+                let dispatch = tree
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .dispatch(&message, span);
+                let result = dispatch.and_then(|dispatch| match dispatch {
+                    Dispatch::Reply(reply, signals) => Ok((reply, signals)),
+                    Dispatch::Closure(pending) => pending
+                        .run(&closure_engine, &message, span)
+                        .map(|reply| (reply, vec![])),
+                });
+                let (reply, signals, error) = match result {
+                    Ok((reply, signals)) => (reply, signals, None),
+                    Err(err) => (err.reply_to(&message), vec![], Some(err)),
+                };
+                let reply = Some(reply).filter(|_| !message.get_no_reply());
+                for outgoing in reply.into_iter().chain(signals) {
+                    if let Err(err) = messages.client().send(outgoing) {
+                        return Some(Value::error(err.into(), span));
+                    }
+                }
+
+                let string_or_nothing =
+                    |s: Option<&str>| s.map(|s| Value::string(s, span)).unwrap_or_default();
+                let args = crate::convert::from_message(&message, span)
+                    .map(|args| Value::list(args, span))
+                    .unwrap_or_default();
+                return Some(Value::record(
+                    record! {
+                        "sender" => string_or_nothing(message.sender().as_deref()),
+                        "path" => string_or_nothing(message.path().as_deref()),
+                        "interface" => string_or_nothing(message.interface().as_deref()),
+                        "member" => string_or_nothing(message.member().as_deref()),
+                        "args" => args,
+                        "error" => error.map(|err| err.to_value(span)).unwrap_or_default(),
+                    },
+                    span,
+                ));
+            }
+        });
+
+        Ok(PipelineData::ListStream(
+            ListStream::new(calls, span, engine.signals().clone()),
+            None,
+        ))
+    }
+}
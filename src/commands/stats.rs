@@ -0,0 +1,152 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
+use nu_protocol::{
+    record, Example, LabeledError, Signature, Span, Spanned, SyntaxShape, Type, Value,
+};
+
+use crate::{config::DbusClientConfig, match_rule::MatchRule, DbusSignatureUtilExt};
+
+pub struct Stats;
+
+impl SimplePluginCommand for Stats {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus stats"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .accepts_timeout()
+            .input_output_type(Type::Nothing, Type::record())
+            .optional(
+                "name",
+                SyntaxShape::String,
+                "Get the statistics of the connection owning this name, instead of the bus",
+            )
+    }
+
+    fn description(&self) -> &str {
+        "Get the statistics of the bus daemon, and the match rules of its connections"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Returns a record with `stats` from GetStats, or GetConnectionStats if a name is given, \
+            and `match_rules` from GetAllMatchRules. Without a name, `match_rules` has a row for \
+            each connection with its unique name and its rules; with a name, it has the rules of \
+            the connection owning the name. Each rule is parsed into a record like \
+            `dbus match-rule` does. If the bus has statistics but not GetAllMatchRules, \
+            `match_rules` is empty and `match_rules_error` says why.
+
+Only dbus-daemon built with --enable-stats has these methods, and it may only allow them for \
+            the user it runs as."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec![
+            "dbus",
+            "stats",
+            "statistics",
+            "debug",
+            "match",
+            "rules",
+            "memory",
+            "leak",
+        ]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                example:
+                    "dbus stats | get match_rules | sort-by -r { $in.rules | length } | first 5",
+                description: "Find the connections with the most match rules",
+                result: None,
+            },
+            Example {
+                example: "(dbus stats org.freedesktop.Notifications).stats.IncomingBytes",
+                description: "Get how many bytes are queued for the notification daemon",
+                result: None,
+            },
+        ]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let config = DbusClientConfig::new(engine, call)?;
+        let dbus = plugin.connections.get_or_connect(engine, config)?;
+        let span = call.head;
+        let name: Option<Spanned<String>> = call.opt(0)?;
+
+        let stats = match &name {
+            Some(name) => dbus.get_connection_stats(name)?,
+            None => dbus.get_stats()?,
+        };
+        let Some(mut all_rules) = dbus.get_all_match_rules()? else {
+            return Ok(Value::record(
+                record! {
+                    "stats" => stats,
+                    "match_rules" => Value::nothing(span),
+                    "match_rules_error" => Value::string(
+                        "The bus doesn't have the GetAllMatchRules method",
+                        span,
+                    ),
+                },
+                span,
+            ));
+        };
+
+        let match_rules = match name {
+            Some(name) => {
+                let owner = dbus.get_name_owner(&name)?;
+                let rules = all_rules.remove(&owner).unwrap_or_default();
+                rules_to_value(rules, span)
+            }
+            None => {
+                let mut connections = all_rules.into_iter().collect::<Vec<_>>();
+                connections.sort();
+                let rows = connections
+                    .into_iter()
+                    .map(|(connection, rules)| {
+                        Value::record(
+                            record! {
+                                "connection" => Value::string(connection, span),
+                                "rules" => rules_to_value(rules, span),
+                            },
+                            span,
+                        )
+                    })
+                    .collect();
+                Value::list(rows, span)
+            }
+        };
+        Ok(Value::record(
+            record! {
+                "stats" => stats,
+                "match_rules" => match_rules,
+                "match_rules_error" => Value::nothing(span),
+            },
+            span,
+        ))
+    }
+}
+
+/// Parse match rules into records, keeping the ones this plugin can't parse as strings
+fn rules_to_value(rules: Vec<String>, span: Span) -> Value {
+    Value::list(
+        rules
+            .into_iter()
+            .map(|rule| match MatchRule::parse(&rule) {
+                Ok(parsed) => parsed.to_value(span),
+                Err(_) => Value::string(rule, span),
+            })
+            .collect(),
+        span,
+    )
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
+use nu_protocol::{Example, LabeledError, Signature, SyntaxShape, Type, Value};
+
+use crate::{config::DbusClientConfig, DbusSignatureUtilExt};
+
+pub struct UpdateActivationEnv;
+
+impl SimplePluginCommand for UpdateActivationEnv {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus update-activation-env"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .accepts_timeout()
+            .input_output_type(Type::Nothing, Type::Nothing)
+            .required(
+                "environment",
+                SyntaxShape::Record(vec![]),
+                "The environment variables to set",
+            )
+    }
+
+    fn description(&self) -> &str {
+        "Set environment variables for the services the bus starts"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Only services started after this are affected. The variables are added to the \
+            environment the bus starts services with, or replace the ones already in it. Values \
+            that aren't strings are converted to strings. Variables can't be removed."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec![
+            "dbus",
+            "activation",
+            "environment",
+            "env",
+            "update",
+            "login",
+        ]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                example: "dbus update-activation-env ($env | select DISPLAY WAYLAND_DISPLAY)",
+                description: "Let services started by the session bus find the display",
+                result: None,
+            },
+            Example {
+                example: "dbus update-activation-env { GTK_THEME: Adwaita:dark }",
+                description: "Set a variable for services started from now on",
+                result: None,
+            },
+        ]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let config = DbusClientConfig::new(engine, call)?;
+        let dbus = plugin.connections.get_or_connect(engine, config)?;
+        let environment: Value = call.req(0)?;
+
+        let environment = environment
+            .as_record()?
+            .iter()
+            .map(|(name, value)| {
+                let value = value.coerce_string().map_err(|err| {
+                    LabeledError::from(err).with_label(
+                        format!("environment variable {name} must be a string"),
+                        value.span(),
+                    )
+                })?;
+                Ok((name.clone(), value))
+            })
+            .collect::<Result<HashMap<_, _>, LabeledError>>()?;
+        dbus.update_activation_environment(&environment)?;
+        Ok(Value::nothing(call.head))
+    }
+}
@@ -0,0 +1,158 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
+use nu_protocol::{Example, LabeledError, Signature, Spanned, SyntaxShape, Type, Value};
+
+use crate::{config::DbusClientConfig, match_rule::MatchRule, DbusSignatureUtilExt};
+
+pub struct WaitSignal;
+
+impl SimplePluginCommand for WaitSignal {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus wait-signal"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .accepts_timeout()
+            .accepts_signal_match_options()
+            .input_output_type(Type::Nothing, Type::Any)
+            .named(
+                "signature",
+                SyntaxShape::String,
+                "Signature of the arguments to send with the method call, in D-Bus format.\n    \
+                 If not provided, they will be determined from introspection.\n    \
+                 If --no-introspect is specified and this is not provided, they will \
+                   be guessed (poorly)",
+                None,
+            )
+            .switch(
+                "no-flatten",
+                "Always return a list of all of the signal's arguments",
+                None,
+            )
+            .named(
+                "cache-ttl",
+                SyntaxShape::Duration,
+                "How long to reuse introspection data for (default 1min). \
+                 Use 0sec to always introspect again",
+                None,
+            )
+            .switch(
+                "no-introspect",
+                "Don't use introspection to determine the correct argument signature",
+                None,
+            )
+            .named(
+                "dest",
+                SyntaxShape::String,
+                "The name of the connection to send the method call to, if any",
+                None,
+            )
+            .optional(
+                "object",
+                SyntaxShape::String,
+                "The path to the object to call a method on before waiting",
+            )
+            .optional(
+                "interface",
+                SyntaxShape::String,
+                "The name of the interface the method belongs to",
+            )
+            .optional(
+                "method",
+                SyntaxShape::String,
+                "The name of the method to send",
+            )
+            .rest(
+                "args",
+                SyntaxShape::Any,
+                "Arguments to send with the method call",
+            )
+    }
+
+    fn description(&self) -> &str {
+        "Wait for a signal, optionally after calling a method"
+    }
+
+    fn extra_description(&self) -> &str {
+        "The subscription is made before the method is called, so a signal sent in response to \
+            the method call can't be missed. Returns the arguments of the first matching signal, \
+            as an array if there is more than one. Fails if no signal arrives before the timeout."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["dbus", "signal", "wait", "response", "block"]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                example: "dbus wait-signal --timeout=1min \
+                    --interface=org.freedesktop.Notifications --member=ActionInvoked",
+                description: "Wait up to a minute for an action on a notification",
+                result: None,
+            },
+            Example {
+                example: "dbus wait-signal --timeout=10sec \
+                    --interface=org.freedesktop.portal.Request --member=Response \
+                    --dest=org.freedesktop.portal.Desktop /org/freedesktop/portal/desktop \
+                    org.freedesktop.portal.Screenshot Screenshot \"\" {}",
+                description: "Request a screenshot and wait for the portal's response",
+                result: None,
+            },
+        ]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let config = DbusClientConfig::new(engine, call)?;
+        let dbus = plugin
+            .connections
+            .private(config)?
+            .with_cache(plugin.cache.clone());
+
+        // Subscribe before calling the method, so that we can't miss the signal
+        let rule = MatchRule::from_signal_flags(call)?;
+        let _subscription = dbus.subscribe(&rule)?;
+
+        if let Some(object) = call.opt::<Spanned<String>>(0)? {
+            let dest = call.get_flag("dest")?.ok_or_else(|| {
+                LabeledError::new("Missing --dest").with_label(
+                    "--dest is required to call a method on this object",
+                    object.span,
+                )
+            })?;
+            dbus.call(
+                &dest,
+                &object,
+                &call.req(1)?,
+                &call.req(2)?,
+                call.get_flag("signature")?.as_ref(),
+                call.positional.get(3..).unwrap_or_default(),
+            )?;
+        }
+
+        let message = dbus.wait_for(&rule, engine.signals())?;
+        let values = crate::convert::from_message(&message, call.head).map_err(|err| {
+            LabeledError::new(err).with_label("while decoding the signal", call.head)
+        })?;
+
+        let flatten = !call.get_flag::<bool>("no-flatten")?.unwrap_or(false);
+
+        // Make the output easier to deal with by returning a list only if there are multiple
+        // arguments
+        match values.len() {
+            0 if flatten => Ok(Value::nothing(call.head)),
+            1 if flatten => Ok(values.into_iter().nth(0).unwrap()),
+            _ => Ok(Value::list(values, call.head)),
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use dbus::{Message, MessageType};
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Example, LabeledError, ListStream, PipelineData, Record, Signature, Span, Spanned, SyntaxShape,
+    Type, Value,
+};
+
+use crate::{
+    client::DbusClient, config::DbusClientConfig, convert, match_rule::MatchRule,
+    DbusSignatureUtilExt,
+};
+
+pub struct Watch;
+
+impl PluginCommand for Watch {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus watch"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .accepts_timeout()
+            .input_output_type(Type::Nothing, Type::List(Type::Record([].into()).into()))
+            .required_named(
+                "dest",
+                SyntaxShape::String,
+                "The name of the connection to watch the properties of",
+                None,
+            )
+            .required(
+                "object",
+                SyntaxShape::String,
+                "The path to the object to watch the properties of",
+            )
+            .required(
+                "interface",
+                SyntaxShape::String,
+                "The name of the interface the properties belong to",
+            )
+    }
+
+    fn description(&self) -> &str {
+        "Watch all of the D-Bus properties of an object for changes"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Returns a stream of records containing all of the properties, starting with the \
+            current state and followed by the full updated state whenever a property changes. \
+            Properties that are invalidated rather than sent with the change are fetched again."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec!["dbus", "property", "watch", "changes", "live"]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                example: "dbus watch --dest=org.mpris.MediaPlayer2.spotify \
+                    /org/mpris/MediaPlayer2 org.mpris.MediaPlayer2.Player | \
+                    each { get Metadata.xesam:title }",
+                description: "Print the title of every song Spotify plays",
+                result: None,
+            },
+            Example {
+                example: "dbus watch --system --dest=org.freedesktop.UPower \
+                    /org/freedesktop/UPower/devices/DisplayDevice \
+                    org.freedesktop.UPower.Device | select Percentage State",
+                description: "Watch the battery level",
+                result: None,
+            },
+        ]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let config = DbusClientConfig::new(engine, call)?;
+        let dbus = plugin.connections.private(config)?;
+        let dest: Spanned<String> = call.get_flag("dest")?.unwrap();
+        let object: Spanned<String> = call.req(0)?;
+        let interface: Spanned<String> = call.req(1)?;
+
+        // Subscribe before taking the snapshot, so that no changes can be missed in between
+        let rule = properties_changed_rule(&dest, &object, &interface)?;
+        let subscription = dbus.subscribe(&rule)?;
+
+        let span = call.head;
+        let mut state = dbus.get_all(&dest, &object, &interface)?.into_record()?;
+
+        let snapshot = Value::record(state.clone(), span);
+        let mut messages = dbus.receive(subscription, engine.signals().clone());
+        let changes = std::iter::from_fn(move || loop {
+            match messages.next()? {
+                Ok(message) if rule.matches(&message) => {
+                    let result = apply_properties_changed(
+                        messages.client(),
+                        &dest,
+                        &object,
+                        &interface,
+                        &mut state,
+                        &message,
+                        span,
+                    );
+                    return Some(match result {
+                        Ok(()) => Value::record(state.clone(), span),
+                        Err(err) => Value::error(err.into(), span),
+                    });
+                }
+                Ok(_) => (),
+                Err(err) => return Some(Value::error(err.into(), span)),
+            }
+        });
+        let updates = std::iter::once(snapshot).chain(changes);
+
+        Ok(PipelineData::ListStream(
+            ListStream::new(updates, span, engine.signals().clone()),
+            None,
+        ))
+    }
+}
+
+/// Make a rule matching `PropertiesChanged` for a specific interface on an object
+fn properties_changed_rule(
+    dest: &Spanned<String>,
+    object: &Spanned<String>,
+    interface: &Spanned<String>,
+) -> Result<MatchRule, LabeledError> {
+    let mut rule = MatchRule {
+        r#type: Some(MessageType::Signal),
+        interface: Some("org.freedesktop.DBus.Properties".into()),
+        member: Some("PropertiesChanged".into()),
+        ..MatchRule::default()
+    };
+    for (key, value) in [("sender", dest), ("path", object), ("arg0", interface)] {
+        rule.set(key, value.item.clone())
+            .map_err(|err| LabeledError::new("Invalid argument").with_label(err, value.span))?;
+    }
+    Ok(rule)
+}
+
+/// Merge the changes from a `PropertiesChanged` signal into the state, fetching the values of
+/// invalidated properties
+fn apply_properties_changed(
+    dbus: &DbusClient,
+    dest: &Spanned<String>,
+    object: &Spanned<String>,
+    interface: &Spanned<String>,
+    state: &mut Record,
+    message: &Message,
+    span: Span,
+) -> Result<(), LabeledError> {
+    let args = convert::from_message(message, span).map_err(|err| {
+        LabeledError::new(err).with_label("while decoding PropertiesChanged", span)
+    })?;
+
+    let (Some(changed), Some(invalidated)) = (args.get(1), args.get(2)) else {
+        return Err(
+            LabeledError::new("PropertiesChanged signal had the wrong type")
+                .with_label("while watching properties", span),
+        );
+    };
+
+    for (name, value) in changed.as_record()? {
+        state.insert(name, value.clone());
+    }
+
+    for name in invalidated.as_list()? {
+        let property = Spanned {
+            item: name.as_str()?.to_owned(),
+            span,
+        };
+        let value = dbus.get(dest, object, interface, &property)?;
+        state.insert(property.item, value);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use dbus::{Message, MessageType};
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    record, Example, LabeledError, ListStream, PipelineData, Signature, Span, SyntaxShape, Type,
+    Value,
+};
+
+use crate::{
+    config::DbusClientConfig, match_rule::MatchRule, pattern::Pattern, DbusSignatureUtilExt,
+};
+
+pub struct WatchNames;
+
+impl PluginCommand for WatchNames {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus watch-names"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .accepts_timeout()
+            .input_output_type(Type::Nothing, Type::List(Type::Record([].into()).into()))
+            .optional(
+                "pattern",
+                SyntaxShape::String,
+                "An optional glob-like pattern to filter the names by",
+            )
+    }
+
+    fn description(&self) -> &str {
+        "Watch connection names appearing on and disappearing from the bus"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Returns a stream with one record per change of ownership of a name. The event is \
+            `appeared` if the name had no owner before, `vanished` if it has no owner now, and \
+            `replaced` if it moved from one owner to another. The pattern works the same way \
+            as for `dbus list`."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec![
+            "dbus", "list", "names", "owner", "watch", "appear", "vanish",
+        ]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                example: "dbus watch-names org.mpris.MediaPlayer2.**",
+                description: "Watch MPRIS2 media players starting and stopping",
+                result: None,
+            },
+            Example {
+                example: "dbus watch-names org.kde.StatusNotifierItem-* | \
+                    where event == vanished",
+                description: "Watch tray icons disappearing",
+                result: None,
+            },
+        ]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let config = DbusClientConfig::new(engine, call)?;
+        let dbus = plugin.connections.private(config)?;
+        let pattern = call
+            .opt::<String>(0)?
+            .map(|pat| Pattern::new(&pat, Some('.')));
+
+        let rule = MatchRule {
+            r#type: Some(MessageType::Signal),
+            sender: Some("org.freedesktop.DBus".into()),
+            path: Some("/org/freedesktop/DBus".into()),
+            interface: Some("org.freedesktop.DBus".into()),
+            member: Some("NameOwnerChanged".into()),
+            ..MatchRule::default()
+        };
+        let subscription = dbus.subscribe(&rule)?;
+
+        let span = call.head;
+        let changes = dbus
+            .receive(subscription, engine.signals().clone())
+            .filter_map(move |result| match result {
+                Ok(message) if rule.matches(&message) => {
+                    let change = name_owner_changed_to_value(&message, span)?;
+                    let name = change.get_data_by_key("name")?;
+                    pattern
+                        .as_ref()
+                        .map_or(true, |p| p.is_match(name.as_str().unwrap_or_default()))
+                        .then_some(change)
+                }
+                Ok(_) => None,
+                Err(err) => Some(Value::error(err.into(), span)),
+            });
+
+        Ok(PipelineData::ListStream(
+            ListStream::new(changes, span, engine.signals().clone()),
+            None,
+        ))
+    }
+}
+
+/// Represent a `NameOwnerChanged` signal as a record describing the event
+fn name_owner_changed_to_value(message: &Message, span: Span) -> Option<Value> {
+    let (name, old_owner, new_owner) = message.read3::<&str, &str, &str>().ok()?;
+
+    let event = match (old_owner.is_empty(), new_owner.is_empty()) {
+        (true, _) => "appeared",
+        (false, true) => "vanished",
+        (false, false) => "replaced",
+    };
+    let owner_or_nothing = |owner: &str| {
+        if owner.is_empty() {
+            Value::nothing(span)
+        } else {
+            Value::string(owner, span)
+        }
+    };
+
+    Some(Value::record(
+        record! {
+            "name" => Value::string(name, span),
+            "old_owner" => owner_or_nothing(old_owner),
+            "new_owner" => owner_or_nothing(new_owner),
+            "event" => Value::string(event, span),
+        },
+        span,
+    ))
+}
@@ -0,0 +1,121 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, SimplePluginCommand};
+use nu_protocol::{record, Example, LabeledError, Signature, Spanned, Type, Value};
+
+use crate::{
+    config::{DbusBusChoice, DbusClientConfig},
+    DbusSignatureUtilExt,
+};
+
+pub struct Whoami;
+
+impl SimplePluginCommand for Whoami {
+    type Plugin = crate::NuPluginDbus;
+
+    fn name(&self) -> &str {
+        "dbus whoami"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .dbus_command()
+            .accepts_dbus_client_options()
+            .accepts_timeout()
+            .input_output_type(Type::Nothing, Type::record())
+    }
+
+    fn description(&self) -> &str {
+        "Describe the connection to the bus, and what's on the other side of it"
+    }
+
+    fn extra_description(&self) -> &str {
+        "Returns the address that was connected to and where it came from, the unique name of \
+            the connection, the IDs of the bus and its machine, the features and interfaces of \
+            the bus, and the credentials the bus has for this connection. The bus-specific \
+            columns are empty for --peer connections."
+    }
+
+    fn search_terms(&self) -> Vec<&str> {
+        vec![
+            "dbus",
+            "whoami",
+            "id",
+            "machine",
+            "credentials",
+            "features",
+            "debug",
+        ]
+    }
+
+    fn examples(&self) -> Vec<Example> {
+        vec![
+            Example {
+                example: "dbus whoami",
+                description: "Find out which session bus this shell talks to",
+                result: None,
+            },
+            Example {
+                example: "(dbus whoami --system).credentials.UnixUserID",
+                description: "Get the uid the system bus knows this connection by",
+                result: None,
+            },
+        ]
+    }
+
+    fn run(
+        &self,
+        plugin: &Self::Plugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: &Value,
+    ) -> Result<Value, LabeledError> {
+        let config = DbusClientConfig::new(engine, call)?;
+        let span = call.head;
+        let (bus, given_address) = config.bus_choice.item.kind_and_address();
+        let (address, source) = match &config.address {
+            Some(address) => (Some(address.address.clone()), Some(address.source.clone())),
+            // Connections from `dbus connect` are already connected
+            None => (given_address.map(String::from), None),
+        };
+        let string_or_nothing =
+            |s: Option<String>| s.map(|s| Value::string(s, span)).unwrap_or_default();
+
+        let is_peer = matches!(config.bus_choice.item, DbusBusChoice::Peer(_));
+        let dbus = plugin.connections.get_or_connect(engine, config)?;
+        let spanned = |item: &str| Spanned {
+            item: item.to_owned(),
+            span,
+        };
+        let unique_name = dbus.channel().unique_name().map(String::from);
+
+        let mut record = record! {
+            "bus" => Value::string(bus, span),
+            "address" => string_or_nothing(address),
+            "source" => string_or_nothing(source),
+            "unique_name" => string_or_nothing(unique_name.clone()),
+        };
+        if is_peer {
+            record.push(
+                "machine_id",
+                Value::string(dbus.get_machine_id(None)?, span),
+            );
+            for column in ["bus_id", "features", "interfaces", "credentials"] {
+                record.push(column, Value::nothing(span));
+            }
+            return Ok(Value::record(record, span));
+        }
+
+        record.push(
+            "machine_id",
+            Value::string(dbus.get_machine_id(Some("org.freedesktop.DBus"))?, span),
+        );
+        record.push("bus_id", Value::string(dbus.get_id()?, span));
+        // Only buses from dbus-daemon 1.12 and later have these properties
+        for (column, property) in [("features", "Features"), ("interfaces", "Interfaces")] {
+            record.push(column, dbus.get_bus_property(property)?.unwrap_or_default());
+        }
+        let credentials =
+            dbus.get_connection_credentials(&spanned(unique_name.as_deref().unwrap_or_default()))?;
+        record.push("credentials", credentials);
+        Ok(Value::record(record, span))
+    }
+}
@@ -1,7 +1,14 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
-use nu_plugin::EvaluatedCall;
-use nu_protocol::{LabeledError, Span, Spanned};
+use nu_plugin::{EngineInterface, EvaluatedCall};
+use nu_protocol::{LabeledError, Span, Spanned, Value};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    address,
+    auth::{Auth, Mechanism},
+    connections::ConnectionHandle,
+};
 
 /// General configuration related to the D-Bus client connection
 #[derive(Debug, Clone)]
@@ -9,14 +16,54 @@ pub struct DbusClientConfig {
     pub span: Span,
     /// Which bus should we connect to?
     pub bus_choice: Spanned<DbusBusChoice>,
-    /// How long to wait for a method call to return
-    pub timeout: Spanned<Duration>,
+    /// How long to wait for a method call to return, if set by a flag or the plugin config
+    pub timeout: Option<Spanned<Duration>>,
     /// Enable introspection if signature unknown (default true)
     pub introspect: bool,
+    /// How long to reuse introspection data for (default 1 minute)
+    pub cache_ttl: Duration,
+    /// The id of a connection from `dbus connect` to use instead of the bus choice
+    pub connection: Option<Spanned<u64>>,
+    /// Signatures to use instead of introspecting, by destination and `interface.member`
+    pub signatures: HashMap<String, HashMap<String, Spanned<String>>>,
+    /// The address of the bus, resolved from the environment of the caller
+    pub address: Option<BusAddress>,
+    /// How to authenticate to a bus or peer given by address, instead of letting libdbus choose
+    pub auth: Option<Spanned<Auth>>,
 }
 
-/// Where to connect to the D-Bus server
+/// The address of a bus, and where it was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BusAddress {
+    pub address: String,
+    pub source: String,
+}
+
+impl std::fmt::Display for BusAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (from {})", self.address, self.source)
+    }
+}
+
+/// The address of the system bus if `DBUS_SYSTEM_BUS_ADDRESS` isn't set
+const DEFAULT_SYSTEM_BUS_ADDRESS: &str = "unix:path=/var/run/dbus/system_bus_socket";
+
+/// Defaults for all commands, from `$env.config.plugins.dbus`
 #[derive(Debug, Clone, Default)]
+pub struct PluginConfig {
+    /// The bus to connect to when no bus flag is given
+    pub bus: Option<Spanned<DbusBusChoice>>,
+    pub timeout: Option<Spanned<Duration>>,
+    pub introspect: Option<bool>,
+    pub cache_ttl: Option<Duration>,
+    /// Names that can be given to `--bus` and `--peer` instead of an address
+    pub buses: HashMap<String, String>,
+    /// Signatures to use instead of introspecting, by destination and `interface.member`
+    pub signatures: HashMap<String, HashMap<String, Spanned<String>>>,
+}
+
+/// Where to connect to the D-Bus server
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum DbusBusChoice {
     /// Connect to the session bus
     #[default]
@@ -29,24 +76,248 @@ pub enum DbusBusChoice {
     Bus(String),
     /// Connect to a non-bus D-Bus server at the given address (will not send Hello)
     Peer(String),
+    /// Connect to a bus through the stdin and stdout of a shell command
+    Exec(String),
 }
 
-impl TryFrom<&EvaluatedCall> for DbusClientConfig {
-    type Error = LabeledError;
+impl DbusBusChoice {
+    /// Find the address of the bus, like libdbus does, but with the environment variables
+    /// looked up by `env`
+    pub fn resolve(&self, env: &dyn Fn(&str) -> Option<String>) -> Result<BusAddress, String> {
+        let from_env = |name: &str| {
+            env(name).map(|address| BusAddress {
+                address,
+                source: format!("$env.{name}"),
+            })
+        };
+        match self {
+            DbusBusChoice::Session => Ok(from_env("DBUS_SESSION_BUS_ADDRESS")
+                .or_else(|| {
+                    // Used by systemd user sessions
+                    let dir = env("XDG_RUNTIME_DIR")?;
+                    let path = std::path::Path::new(&dir).join("bus");
+                    path.exists().then(|| BusAddress {
+                        address: format!("unix:path={}", path.display()),
+                        source: "$env.XDG_RUNTIME_DIR".into(),
+                    })
+                })
+                .unwrap_or_else(|| {
+                    // Let libdbus find or start a session bus, e.g. through X11
+                    let reason = match env("XDG_RUNTIME_DIR") {
+                        Some(dir) => format!("there is no socket at {dir}/bus"),
+                        None => {
+                            "neither DBUS_SESSION_BUS_ADDRESS nor XDG_RUNTIME_DIR is set".into()
+                        }
+                    };
+                    BusAddress {
+                        address: "autolaunch:".into(),
+                        source: format!("libdbus autolaunch, because {reason}"),
+                    }
+                })),
+            DbusBusChoice::System => {
+                Ok(
+                    from_env("DBUS_SYSTEM_BUS_ADDRESS").unwrap_or_else(|| BusAddress {
+                        address: DEFAULT_SYSTEM_BUS_ADDRESS.into(),
+                        source: "the default system bus address".into(),
+                    }),
+                )
+            }
+            DbusBusChoice::Started => from_env("DBUS_STARTER_ADDRESS")
+                .map(Ok)
+                .or_else(|| match env("DBUS_STARTER_BUS_TYPE")?.as_str() {
+                    "session" => Some(DbusBusChoice::Session.resolve(env)),
+                    "system" => Some(DbusBusChoice::System.resolve(env)),
+                    _ => None,
+                })
+                .unwrap_or_else(|| {
+                    Err("This process wasn't started by a bus, \
+                        because DBUS_STARTER_ADDRESS is not set"
+                        .into())
+                }),
+            DbusBusChoice::Bus(address) | DbusBusChoice::Peer(address) => Ok(BusAddress {
+                address: address.clone(),
+                source: "the given address".into(),
+            }),
+            DbusBusChoice::Exec(command) => Ok(BusAddress {
+                address: format!(
+                    "unixexec:path=/bin/sh,argv0=sh,argv1=-c,argv2={}",
+                    address::escape(command)
+                ),
+                source: "the given command".into(),
+            }),
+        }
+    }
+
+    /// The kind of bus, and its address if it was given explicitly
+    pub fn kind_and_address(&self) -> (&'static str, Option<&str>) {
+        match self {
+            DbusBusChoice::Session => ("session", None),
+            DbusBusChoice::System => ("system", None),
+            DbusBusChoice::Started => ("started", None),
+            DbusBusChoice::Bus(address) => ("bus", Some(address)),
+            DbusBusChoice::Peer(address) => ("peer", Some(address)),
+            DbusBusChoice::Exec(command) => ("exec", Some(command)),
+        }
+    }
+}
+
+impl PluginConfig {
+    /// Get the plugin configuration from the engine
+    pub fn get(engine: &EngineInterface) -> Result<PluginConfig, LabeledError> {
+        match engine.get_plugin_config()? {
+            Some(value) => PluginConfig::from_value(&value),
+            None => Ok(PluginConfig::default()),
+        }
+    }
 
-    fn try_from(call: &EvaluatedCall) -> Result<Self, Self::Error> {
+    /// Parse the plugin configuration, in the form `{ bus, timeout, introspect, cache_ttl,
+    /// buses: { <name>: <address> }, signatures: { <dest>: { <interface.member>: <signature> } } }`
+    pub fn from_value(value: &Value) -> Result<PluginConfig, LabeledError> {
+        let mut config = PluginConfig::default();
+        let mut bus = None;
+        for (key, value) in value.as_record()? {
+            match &key[..] {
+                "bus" => bus = Some(value),
+                "timeout" => {
+                    config.timeout = Some(Spanned {
+                        item: to_std_duration(value, "Timeout must be a positive duration")?,
+                        span: value.span(),
+                    })
+                }
+                "introspect" => config.introspect = Some(value.as_bool()?),
+                "cache_ttl" => {
+                    config.cache_ttl =
+                        Some(to_std_duration(value, "Cache TTL must not be negative")?)
+                }
+                "buses" => {
+                    for (name, address) in value.as_record()? {
+                        address::parse_spanned(address.as_str()?, address.span())?;
+                        config.buses.insert(name.clone(), address.as_str()?.into());
+                    }
+                }
+                "signatures" => {
+                    for (dest, members) in value.as_record()? {
+                        let members = members
+                            .as_record()?
+                            .iter()
+                            .map(|(member, signature)| {
+                                let signature = Spanned {
+                                    item: signature.as_str()?.into(),
+                                    span: signature.span(),
+                                };
+                                Ok((member.clone(), signature))
+                            })
+                            .collect::<Result<_, LabeledError>>()?;
+                        config.signatures.insert(dest.clone(), members);
+                    }
+                }
+                _ => {
+                    return Err(LabeledError::new(format!(
+                        "Unknown key {key:?} in the dbus plugin config"
+                    ))
+                    .with_label(
+                        "expected `bus`, `timeout`, `introspect`, `cache_ttl`, `buses`, \
+                            or `signatures`",
+                        value.span(),
+                    ))
+                }
+            }
+        }
+
+        // Resolved last, so that it can refer to one of the named buses
+        if let Some(bus) = bus {
+            let item = match bus.as_str()? {
+                "session" => DbusBusChoice::Session,
+                "system" => DbusBusChoice::System,
+                "started" => DbusBusChoice::Started,
+                name => {
+                    let address = config.resolve_bus(name);
+                    if address == name {
+                        address::parse_spanned(address, bus.span())?;
+                    }
+                    DbusBusChoice::Bus(address.into())
+                }
+            };
+            config.bus = Some(Spanned {
+                item,
+                span: bus.span(),
+            });
+        }
+        Ok(config)
+    }
+
+    /// Look up the address of a named bus, or return the address given
+    fn resolve_bus<'a>(&'a self, address: &'a str) -> &'a str {
+        self.buses.get(address).map(|s| &s[..]).unwrap_or(address)
+    }
+}
+
+/// Convert a nushell duration to a non-negative [Duration]
+fn to_std_duration(value: &Value, negative_msg: &str) -> Result<Duration, LabeledError> {
+    let nanos: u64 = value.as_duration()?.try_into().map_err(|_| {
+        LabeledError::new(negative_msg).with_label("invalid duration specified here", value.span())
+    })?;
+    Ok(Duration::from_nanos(nanos))
+}
+
+impl DbusClientConfig {
+    /// Get the configuration for a command, from its flags and the plugin config
+    pub fn new(engine: &EngineInterface, call: &EvaluatedCall) -> Result<Self, LabeledError> {
+        let mut config = DbusClientConfig::from_call(call, PluginConfig::get(engine)?)?;
+        let env = |name: &str| {
+            let value = engine.get_env_var(name).ok()??;
+            value.coerce_into_string().ok()
+        };
+        if let Some(auth) = &mut config.auth {
+            auth.item.resolve(&env);
+        }
+        // A connection from `dbus connect` is already connected
+        if config.connection.is_none() {
+            config.address = Some(config.bus_choice.item.resolve(&env).map_err(|err| {
+                LabeledError::new(err).with_label("while finding this bus", config.bus_choice.span)
+            })?);
+        }
+        Ok(config)
+    }
+
+    /// How long to wait for a method call to return (default 2 seconds)
+    pub fn timeout(&self) -> Spanned<Duration> {
+        self.timeout.unwrap_or(Spanned {
+            item: Duration::from_secs(2),
+            span: self.span,
+        })
+    }
+
+    /// The address of the bus. If it wasn't resolved with the caller's environment, only explicit
+    /// addresses can be used
+    pub fn resolved_address(&self) -> Result<BusAddress, LabeledError> {
+        match &self.address {
+            Some(address) => Ok(address.clone()),
+            None => self.bus_choice.item.resolve(&|_| None).map_err(|err| {
+                LabeledError::new(err).with_label("while finding this bus", self.bus_choice.span)
+            }),
+        }
+    }
+
+    /// Get the configuration from the flags of a command, with defaults from the plugin config
+    pub fn from_call(call: &EvaluatedCall, plugin: PluginConfig) -> Result<Self, LabeledError> {
         let mut config = DbusClientConfig {
             span: call.head,
-            bus_choice: Spanned {
+            bus_choice: plugin.bus.clone().unwrap_or(Spanned {
                 item: DbusBusChoice::default(),
                 span: call.head,
-            },
-            timeout: Spanned {
-                item: Duration::from_secs(2),
-                span: call.head,
-            },
-            introspect: true,
+            }),
+            timeout: plugin.timeout,
+            introspect: plugin.introspect.unwrap_or(true),
+            cache_ttl: plugin.cache_ttl.unwrap_or(Duration::from_secs(60)),
+            connection: None,
+            signatures: HashMap::new(),
+            address: None,
+            auth: None,
         };
+        let mut identity = None;
+        // The span of a flag choosing the bus, which can't be combined with --connection
+        let mut bus_flag = None;
 
         // Handle recognized config args
         for (name, value) in &call.named {
@@ -63,11 +334,17 @@ impl TryFrom<&EvaluatedCall> for DbusClientConfig {
                             item: dest,
                             span: name.span,
                         };
+                        bus_flag = Some(name.span);
                     }
                 }
                 r#type @ ("bus" | "peer") => {
                     if let Some(value) = value {
-                        let address = value.as_str()?;
+                        let name = value.as_str()?;
+                        let address = plugin.resolve_bus(name);
+                        // Named buses were checked when reading the plugin config
+                        if address == name {
+                            address::parse_spanned(address, value.span())?;
+                        }
                         let dest = match r#type {
                             "bus" => DbusBusChoice::Bus(address.to_owned()),
                             "peer" => DbusBusChoice::Peer(address.to_owned()),
@@ -77,31 +354,343 @@ impl TryFrom<&EvaluatedCall> for DbusClientConfig {
                             item: dest,
                             span: value.span(),
                         };
+                        bus_flag = Some(value.span());
+                    }
+                }
+                "exec" => {
+                    if let Some(value) = value {
+                        config.bus_choice = Spanned {
+                            item: DbusBusChoice::Exec(value.as_str()?.into()),
+                            span: value.span(),
+                        };
+                        bus_flag = Some(value.span());
                     }
                 }
                 "timeout" => {
                     if let Some(value) = value {
-                        let nanos: u64 = value.as_duration()?.try_into().map_err(|_| {
-                            LabeledError::new("Timeout must be a positive duration")
-                                .with_label("invalid timeout specified here", value.span())
-                        })?;
-                        let item = Duration::from_nanos(nanos);
-                        config.timeout = Spanned {
-                            item,
+                        config.timeout = Some(Spanned {
+                            item: to_std_duration(value, "Timeout must be a positive duration")?,
+                            span: value.span(),
+                        });
+                    }
+                }
+                "connection" => {
+                    if let Some(value) = value {
+                        let handle = value
+                            .as_custom_value()?
+                            .as_any()
+                            .downcast_ref::<ConnectionHandle>()
+                            .ok_or_else(|| {
+                                LabeledError::new("Not a D-Bus connection").with_label(
+                                    "expected a connection from `dbus connect`",
+                                    value.span(),
+                                )
+                            })?;
+                        config.bus_choice = Spanned {
+                            item: handle.bus.clone(),
                             span: value.span(),
                         };
+                        config.connection = Some(Spanned {
+                            item: handle.id,
+                            span: value.span(),
+                        });
+                    }
+                }
+                "auth" => {
+                    if let Some(value) = value {
+                        let mechanism = Mechanism::parse(value.as_str()?).ok_or_else(|| {
+                            LabeledError::new("Unknown authentication mechanism").with_label(
+                                "expected EXTERNAL, ANONYMOUS or DBUS_COOKIE_SHA1",
+                                value.span(),
+                            )
+                        })?;
+                        config.auth = Some(Spanned {
+                            item: Auth {
+                                mechanism,
+                                identity: None,
+                                home: None,
+                            },
+                            span: value.span(),
+                        });
+                    }
+                }
+                "identity" => {
+                    if let Some(value) = value {
+                        identity = Some(Spanned {
+                            item: value.as_str()?.to_owned(),
+                            span: value.span(),
+                        });
+                    }
+                }
+                "cache-ttl" => {
+                    if let Some(value) = value {
+                        config.cache_ttl =
+                            to_std_duration(value, "Cache TTL must not be negative")?;
                     }
                 }
                 "no-introspect" => {
-                    config.introspect = !value
-                        .as_ref()
-                        .and_then(|v| v.as_bool().ok())
-                        .unwrap_or(false);
+                    if value.is_none() || value.as_ref().is_some_and(|v| v.is_true()) {
+                        config.introspect = false;
+                    }
                 }
                 _ => (),
             }
         }
 
+        // An identity alone is for the default mechanism
+        if let Some(identity) = identity {
+            let auth = config.auth.get_or_insert(Spanned {
+                item: Auth {
+                    mechanism: Mechanism::External,
+                    identity: None,
+                    home: None,
+                },
+                span: identity.span,
+            });
+            auth.item.identity = Some(identity.item);
+        }
+        if let (Some(connection), Some(bus_flag)) = (&config.connection, bus_flag) {
+            return Err(LabeledError::new(
+                "A connection from `dbus connect` can't be combined with choosing a bus",
+            )
+            .with_label("already connected to a bus", connection.span)
+            .with_label("can't also connect to this", bus_flag));
+        }
+        if let Some(auth) = &config.auth {
+            if !matches!(
+                config.bus_choice.item,
+                DbusBusChoice::Bus(_) | DbusBusChoice::Peer(_)
+            ) || config.connection.is_some()
+            {
+                return Err(LabeledError::new(
+                    "Authentication can only be chosen with --bus or --peer",
+                )
+                .with_label("can't be used with this bus", auth.span)
+                .with_label("connecting to this", config.bus_choice.span));
+            }
+        }
+
+        config.signatures = plugin.signatures;
         Ok(config)
     }
+
+    /// The signature configured for a method or property of a destination, if any
+    pub fn signature_override(
+        &self,
+        dest: &str,
+        interface: &str,
+        member: &str,
+    ) -> Option<&Spanned<String>> {
+        self.signatures
+            .get(dest)?
+            .get(&format!("{interface}.{member}"))
+    }
+}
+
+#[test]
+fn test_plugin_config() {
+    use nu_protocol::record;
+
+    let value = Value::test_record(record!(
+        "bus" => Value::test_string("work"),
+        "timeout" => Value::test_duration(10_000_000_000),
+        "introspect" => Value::test_bool(false),
+        "buses" => Value::test_record(record!(
+            "work" => Value::test_string("unix:path=/run/work/bus"),
+        )),
+        "signatures" => Value::test_record(record!(
+            "com.example" => Value::test_record(record!(
+                "com.example.Foo.Bar" => Value::test_string("su"),
+            )),
+        )),
+    ));
+    let config = PluginConfig::from_value(&value).unwrap();
+    assert_eq!(
+        Some(DbusBusChoice::Bus("unix:path=/run/work/bus".into())),
+        config.bus.map(|bus| bus.item)
+    );
+    assert_eq!(
+        Some(Duration::from_secs(10)),
+        config.timeout.map(|timeout| timeout.item)
+    );
+    assert_eq!(Some(false), config.introspect);
+
+    let bad = Value::test_record(record!("colour" => Value::test_string("blue")));
+    assert!(PluginConfig::from_value(&bad).is_err());
+}
+
+#[test]
+fn test_from_call_with_plugin_config() {
+    let span = Span::test_data();
+    let plugin = PluginConfig {
+        bus: Some(Spanned {
+            item: DbusBusChoice::System,
+            span,
+        }),
+        introspect: Some(false),
+        buses: [("work".into(), "unix:path=/run/work/bus".into())].into(),
+        signatures: [(
+            "com.example".into(),
+            [(
+                "com.example.Foo.Bar".into(),
+                Spanned {
+                    item: "su".into(),
+                    span,
+                },
+            )]
+            .into(),
+        )]
+        .into(),
+        ..PluginConfig::default()
+    };
+
+    // Defaults from the plugin config
+    let config = DbusClientConfig::from_call(&EvaluatedCall::new(span), plugin.clone()).unwrap();
+    assert_eq!(DbusBusChoice::System, config.bus_choice.item);
+    assert_eq!(None, config.timeout);
+    assert_eq!(Duration::from_secs(2), config.timeout().item);
+    assert!(!config.introspect);
+    assert_eq!(
+        Some("su"),
+        config
+            .signature_override("com.example", "com.example.Foo", "Bar")
+            .map(|sig| &sig.item[..])
+    );
+    assert_eq!(
+        None,
+        config.signature_override("com.example", "com.example.Foo", "Baz")
+    );
+
+    // A timeout from the plugin config is kept as set
+    let timeout = Spanned {
+        item: Duration::from_secs(10),
+        span,
+    };
+    let with_timeout = PluginConfig {
+        timeout: Some(timeout),
+        ..plugin.clone()
+    };
+    let config = DbusClientConfig::from_call(&EvaluatedCall::new(span), with_timeout).unwrap();
+    assert_eq!(Some(timeout), config.timeout);
+
+    // Flags override them, and can refer to named buses
+    let call = EvaluatedCall::new(span)
+        .with_named(Spanned { item: "bus", span }, Value::test_string("work"));
+    let config = DbusClientConfig::from_call(&call, plugin).unwrap();
+    assert_eq!(
+        DbusBusChoice::Bus("unix:path=/run/work/bus".into()),
+        config.bus_choice.item
+    );
+}
+
+#[test]
+fn test_from_call_with_connection() {
+    let span = Span::test_data();
+    let handle = ConnectionHandle {
+        id: 1,
+        bus: DbusBusChoice::Session,
+        unique_name: Some(":1.42".into()),
+    };
+    let call = EvaluatedCall::new(span).with_named(
+        Spanned {
+            item: "connection",
+            span,
+        },
+        handle.clone().into_value(span),
+    );
+    let config = DbusClientConfig::from_call(&call, PluginConfig::default()).unwrap();
+    assert_eq!(Some(1), config.connection.map(|id| id.item));
+    assert_eq!(DbusBusChoice::Session, config.bus_choice.item);
+
+    // Choosing a bus as well is ambiguous
+    let with_system = call.clone().with_flag(Spanned {
+        item: "system",
+        span,
+    });
+    assert!(DbusClientConfig::from_call(&with_system, PluginConfig::default()).is_err());
+    let with_bus = call.with_named(
+        Spanned { item: "bus", span },
+        Value::test_string("unix:path=/run/work/bus"),
+    );
+    assert!(DbusClientConfig::from_call(&with_bus, PluginConfig::default()).is_err());
+
+    // An explicitly disabled flag doesn't choose a bus
+    let handle_value = handle.into_value(span);
+    let call = EvaluatedCall::new(span)
+        .with_named(
+            Spanned {
+                item: "connection",
+                span,
+            },
+            handle_value,
+        )
+        .with_named(
+            Spanned {
+                item: "system",
+                span,
+            },
+            Value::test_bool(false),
+        );
+    assert!(DbusClientConfig::from_call(&call, PluginConfig::default()).is_ok());
+}
+
+#[test]
+fn test_resolve_address() {
+    let env = |name: &str| match name {
+        "DBUS_SESSION_BUS_ADDRESS" => Some("unix:path=/run/user/1000/bus".to_string()),
+        "DBUS_STARTER_BUS_TYPE" => Some("system".to_string()),
+        _ => None,
+    };
+    assert_eq!(
+        Ok(BusAddress {
+            address: "unix:path=/run/user/1000/bus".into(),
+            source: "$env.DBUS_SESSION_BUS_ADDRESS".into(),
+        }),
+        DbusBusChoice::Session.resolve(&env)
+    );
+    let system = BusAddress {
+        address: DEFAULT_SYSTEM_BUS_ADDRESS.into(),
+        source: "the default system bus address".into(),
+    };
+    assert_eq!(Ok(system.clone()), DbusBusChoice::System.resolve(&env));
+    // Falls back to the bus named by DBUS_STARTER_BUS_TYPE
+    assert_eq!(Ok(system), DbusBusChoice::Started.resolve(&env));
+    assert_eq!(
+        "unix:path=/tmp/bus",
+        DbusBusChoice::Peer("unix:path=/tmp/bus".into())
+            .resolve(&env)
+            .unwrap()
+            .address
+    );
+
+    // Commands are run by the shell
+    let exec = DbusBusChoice::Exec("ssh host systemd-stdio-bridge".into())
+        .resolve(&env)
+        .unwrap();
+    assert_eq!(
+        Ok(vec![address::Address {
+            transport: address::Transport::UnixExec {
+                path: "/bin/sh".into(),
+                argv: vec![
+                    "sh".into(),
+                    "-c".into(),
+                    "ssh host systemd-stdio-bridge".into()
+                ],
+            },
+            guid: None,
+        }]),
+        address::parse(&exec.address)
+    );
+
+    let empty = |_: &str| None;
+    assert_eq!(
+        "autolaunch:",
+        DbusBusChoice::Session.resolve(&empty).unwrap().address
+    );
+    let no_socket = |name: &str| (name == "XDG_RUNTIME_DIR").then(|| "/nonexistent".to_string());
+    assert_eq!(
+        "libdbus autolaunch, because there is no socket at /nonexistent/bus",
+        DbusBusChoice::Session.resolve(&no_socket).unwrap().source
+    );
+    assert!(DbusBusChoice::Started.resolve(&empty).is_err());
 }
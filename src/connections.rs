@@ -0,0 +1,252 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use dbus::channel::Channel;
+use nu_plugin::EngineInterface;
+use nu_protocol::{record, CustomValue, LabeledError, ShellError, Span, Value};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::Auth,
+    client::DbusClient,
+    config::{DbusBusChoice, DbusClientConfig},
+};
+
+/// Connections that outlive a single command, so they don't have to be set up again for each
+/// command, and keep the names they own
+#[derive(Default)]
+pub struct Connections {
+    /// The connection to each bus
+    channels: Mutex<HashMap<DbusBusChoice, Pooled>>,
+    /// Connections opened with `dbus connect`, by the id of their handle
+    handles: Mutex<HashMap<u64, Arc<Channel>>>,
+    next_id: AtomicU64,
+}
+
+/// A persistent connection, with how it was opened
+struct Pooled {
+    address: String,
+    auth: Option<Auth>,
+    channel: Arc<Channel>,
+}
+
+impl Pooled {
+    /// Whether the connection is still usable for the config. It isn't if the environment points
+    /// somewhere else now, or another mechanism was chosen
+    fn matches(&self, config: &DbusClientConfig, address: &str) -> bool {
+        self.channel.is_connected()
+            && self.address == address
+            && self.auth.as_ref() == config.auth.as_ref().map(|auth| &auth.item)
+    }
+
+    /// Hand out the connection, dropping the messages queued on it first. Replies are taken by
+    /// the calls waiting for them, so these are signals (like `NameAcquired`) and calls that
+    /// nobody is receiving, which would otherwise pile up for as long as the connection is open.
+    /// They're kept if the connection is still in use, e.g. by `dbus serve`
+    fn take_channel(&self) -> Arc<Channel> {
+        if Arc::strong_count(&self.channel) == 1 {
+            // A closed connection fails the next call with a better error
+            let _ = self.channel.read_write(Some(Duration::ZERO));
+            while self.channel.pop_message().is_some() {}
+        }
+        self.channel.clone()
+    }
+}
+
+/// A connection opened with `dbus connect`, which is closed when the value is dropped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionHandle {
+    pub id: u64,
+    pub bus: DbusBusChoice,
+    pub unique_name: Option<String>,
+}
+
+impl Connections {
+    /// Get a client using the persistent connection to the configured bus, connecting first if
+    /// there isn't one yet or it was closed
+    pub fn get_or_connect(
+        &self,
+        engine: &EngineInterface,
+        config: DbusClientConfig,
+    ) -> Result<DbusClient, LabeledError> {
+        if let Some(channel) = self.handle_channel(&config)? {
+            return Ok(DbusClient::with_channel(config, channel));
+        }
+        let address = config.resolved_address()?.address;
+        let mut channels = self.channels.lock().unwrap_or_else(|err| err.into_inner());
+        let channel = match channels.get(&config.bus_choice.item) {
+            Some(pooled) if pooled.matches(&config, &address) => pooled.take_channel(),
+            _ => {
+                let channel = Arc::new(DbusClient::connect(&config)?);
+                // The connections would be closed if the plugin were stopped
+                engine.set_gc_disabled(true)?;
+                let pooled = Pooled {
+                    address,
+                    auth: config.auth.as_ref().map(|auth| auth.item.clone()),
+                    channel,
+                };
+                let channel = pooled.take_channel();
+                channels.insert(config.bus_choice.item.clone(), pooled);
+                channel
+            }
+        };
+        Ok(DbusClient::with_channel(config, channel))
+    }
+
+    /// Get a client using the persistent connection to the configured bus, if there is one
+    pub fn get(&self, config: DbusClientConfig) -> Result<Option<DbusClient>, LabeledError> {
+        if let Some(channel) = self.handle_channel(&config)? {
+            return Ok(Some(DbusClient::with_channel(config, channel)));
+        }
+        let address = config.resolved_address()?.address;
+        let channels = self.channels.lock().unwrap_or_else(|err| err.into_inner());
+        Ok(channels
+            .get(&config.bus_choice.item)
+            .filter(|pooled| pooled.matches(&config, &address))
+            .map(|pooled| DbusClient::with_channel(config.clone(), pooled.take_channel())))
+    }
+
+    /// Get a client with a connection of its own, for commands that change the state of the
+    /// connection, unless a connection from `dbus connect` was given
+    pub fn private(&self, config: DbusClientConfig) -> Result<DbusClient, LabeledError> {
+        match self.handle_channel(&config)? {
+            Some(channel) => Ok(DbusClient::with_channel(config, channel)),
+            None => DbusClient::new(config),
+        }
+    }
+
+    /// Open a new connection, kept open until its handle is dropped
+    pub fn connect(
+        &self,
+        engine: &EngineInterface,
+        config: &DbusClientConfig,
+    ) -> Result<ConnectionHandle, LabeledError> {
+        let channel = DbusClient::connect(config)?;
+        let handle = ConnectionHandle {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            bus: config.bus_choice.item.clone(),
+            unique_name: channel.unique_name().map(String::from),
+        };
+        let mut handles = self.handles.lock().unwrap_or_else(|err| err.into_inner());
+        engine.set_gc_disabled(true)?;
+        handles.insert(handle.id, Arc::new(channel));
+        Ok(handle)
+    }
+
+    /// Close the persistent connection to a bus, or all of them if `bus` is `None`. Returns
+    /// whether any were open
+    pub fn disconnect(
+        &self,
+        engine: &EngineInterface,
+        bus: Option<&DbusBusChoice>,
+    ) -> Result<bool, LabeledError> {
+        let mut channels = self.channels.lock().unwrap_or_else(|err| err.into_inner());
+        let any = match bus {
+            Some(bus) => channels.remove(bus).is_some(),
+            None => channels.drain().count() > 0,
+        };
+        drop(channels);
+        self.release_gc(engine)?;
+        Ok(any)
+    }
+
+    /// Close the connection of a handle from `dbus connect`. Returns whether it was open
+    pub fn drop_handle(&self, engine: &EngineInterface, id: u64) -> Result<bool, LabeledError> {
+        let mut handles = self.handles.lock().unwrap_or_else(|err| err.into_inner());
+        let any = handles.remove(&id).is_some();
+        drop(handles);
+        self.release_gc(engine)?;
+        Ok(any)
+    }
+
+    /// Find the connection of the handle given with `--connection`, if any
+    fn handle_channel(
+        &self,
+        config: &DbusClientConfig,
+    ) -> Result<Option<Arc<Channel>>, LabeledError> {
+        let Some(id) = &config.connection else {
+            return Ok(None);
+        };
+        let handles = self.handles.lock().unwrap_or_else(|err| err.into_inner());
+        match handles.get(&id.item) {
+            Some(channel) => Ok(Some(channel.clone())),
+            None => Err(LabeledError::new("Connection is closed")
+                .with_label("this connection was closed with `dbus disconnect`", id.span)),
+        }
+    }
+
+    /// Let the plugin be stopped if there are no connections left to keep open
+    fn release_gc(&self, engine: &EngineInterface) -> Result<(), LabeledError> {
+        let channels = self.channels.lock().unwrap_or_else(|err| err.into_inner());
+        let handles = self.handles.lock().unwrap_or_else(|err| err.into_inner());
+        if channels.is_empty() && handles.is_empty() {
+            engine.set_gc_disabled(false)?;
+        }
+        Ok(())
+    }
+}
+
+impl ConnectionHandle {
+    pub fn into_value(self, span: Span) -> Value {
+        Value::custom(Box::new(self), span)
+    }
+}
+
+#[typetag::serde]
+impl CustomValue for ConnectionHandle {
+    fn clone_value(&self, span: Span) -> Value {
+        self.clone().into_value(span)
+    }
+
+    fn type_name(&self) -> String {
+        "DbusConnection".into()
+    }
+
+    fn to_base_value(&self, span: Span) -> Result<Value, ShellError> {
+        let (bus, address) = self.bus.kind_and_address();
+        let string_or_nothing =
+            |s: Option<&str>| s.map(|s| Value::string(s, span)).unwrap_or_default();
+        Ok(Value::record(
+            record! {
+                "bus" => Value::string(bus, span),
+                "address" => string_or_nothing(address),
+                "unique_name" => string_or_nothing(self.unique_name.as_deref()),
+            },
+            span,
+        ))
+    }
+
+    fn follow_path_string(
+        &self,
+        self_span: Span,
+        column_name: String,
+        path_span: Span,
+    ) -> Result<Value, ShellError> {
+        self.to_base_value(self_span)?
+            .into_record()?
+            .remove(&column_name)
+            .ok_or(ShellError::CantFindColumn {
+                col_name: column_name,
+                span: Some(path_span),
+                src_span: self_span,
+            })
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn as_mut_any(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+
+    fn notify_plugin_on_drop(&self) -> bool {
+        true
+    }
+}
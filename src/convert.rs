@@ -1,11 +1,12 @@
+use chrono::{DateTime, FixedOffset};
 use dbus::{
     arg::{
         messageitem::{MessageItem, MessageItemArray, MessageItemDict},
         ArgType, RefArg,
     },
-    Message, Signature,
+    Message, MessageType, Signature,
 };
-use nu_protocol::{LabeledError, Record, Span, Value};
+use nu_protocol::{record, LabeledError, Record, Span, Value};
 use std::str::FromStr;
 
 use crate::dbus_type::DbusType;
@@ -19,6 +20,67 @@ pub fn from_message(message: &Message, span: Span) -> Result<Vec<Value>, String>
     Ok(out)
 }
 
+/// Get the signature of all of the arguments of a message
+pub fn message_signature(message: &Message) -> String {
+    let mut iter = message.iter_init();
+    let mut signature = String::new();
+    while iter.arg_type() != ArgType::Invalid {
+        signature.push_str(&iter.signature());
+        iter.next();
+    }
+    signature
+}
+
+/// Get the name of a message type, as used in match rules
+pub fn message_type_name(r#type: MessageType) -> &'static str {
+    match r#type {
+        MessageType::MethodCall => "method_call",
+        MessageType::MethodReturn => "method_return",
+        MessageType::Error => "error",
+        MessageType::Signal => "signal",
+    }
+}
+
+/// Get the error name of an error message
+pub fn message_error_name(message: &Message) -> Option<String> {
+    if message.msg_type() != MessageType::Error {
+        return None;
+    }
+    // The error name isn't otherwise accessible without a mutable message
+    let mut copy = message.duplicate().ok()?;
+    let err = copy.as_result().err()?;
+    err.name().map(|name| name.to_owned())
+}
+
+/// Represent a whole message, including its header fields, as a nushell record
+pub fn message_to_record(
+    message: &Message,
+    timestamp: DateTime<FixedOffset>,
+    span: Span,
+) -> Result<Value, String> {
+    let string_or_nothing = |s: Option<&str>| s.map(|s| Value::string(s, span)).unwrap_or_default();
+    let int_or_nothing = |i: Option<u32>| i.map(|i| Value::int(i.into(), span)).unwrap_or_default();
+
+    Ok(Value::record(
+        record! {
+            "timestamp" => Value::date(timestamp, span),
+            "type" => Value::string(message_type_name(message.msg_type()), span),
+            "serial" => int_or_nothing(message.get_serial()),
+            "reply_serial" => int_or_nothing(message.get_reply_serial()),
+            "no_reply" => Value::bool(message.get_no_reply(), span),
+            "sender" => string_or_nothing(message.sender().as_deref()),
+            "destination" => string_or_nothing(message.destination().as_deref()),
+            "path" => string_or_nothing(message.path().as_deref()),
+            "interface" => string_or_nothing(message.interface().as_deref()),
+            "member" => string_or_nothing(message.member().as_deref()),
+            "error_name" => string_or_nothing(message_error_name(message).as_deref()),
+            "signature" => Value::string(message_signature(message), span),
+            "body" => Value::list(from_message(message, span)?, span),
+        },
+        span,
+    ))
+}
+
 pub fn from_refarg(refarg: &dyn RefArg, span: Span) -> Result<Value, String> {
     Ok(match refarg.arg_type() {
         ArgType::Array => {
@@ -1,3 +1,5 @@
+use std::fmt::Write;
+
 use nu_protocol::{record, Span, Value};
 use serde::Deserialize;
 
@@ -7,6 +9,58 @@ macro_rules! list_to_value {
     };
 }
 
+const DOCTYPE: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+  "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+"#;
+
+/// Writes XML elements with indentation
+struct XmlWriter {
+    out: String,
+    depth: usize,
+}
+
+impl XmlWriter {
+    /// Write an element with the given attributes, and children written by `children` if any
+    fn element(
+        &mut self,
+        name: &str,
+        attrs: &[(&str, Option<&str>)],
+        children: Option<&dyn Fn(&mut XmlWriter)>,
+    ) {
+        write!(self.out, "{:1$}<{name}", "", self.depth * 2).unwrap();
+        for (attr, value) in attrs {
+            if let Some(value) = value {
+                write!(self.out, " {attr}=\"{}\"", escape_xml(value)).unwrap();
+            }
+        }
+        if let Some(children) = children {
+            self.out.push_str(">\n");
+            self.depth += 1;
+            children(self);
+            self.depth -= 1;
+            writeln!(self.out, "{:1$}</{name}>", "", self.depth * 2).unwrap();
+        } else {
+            self.out.push_str("/>\n");
+        }
+    }
+
+    /// Write a list of elements, as children of another element
+    fn list<T>(&mut self, items: &[T], write: impl Fn(&T, &mut XmlWriter)) {
+        for item in items {
+            write(item, self);
+        }
+    }
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq, Default)]
 #[serde(rename_all = "kebab-case")]
 pub struct Node {
@@ -25,6 +79,32 @@ impl Node {
         Node::deserialize(&mut deserializer)
     }
 
+    /// Serialize the node to the introspection XML format
+    pub fn to_xml(&self) -> String {
+        let mut writer = XmlWriter {
+            out: DOCTYPE.into(),
+            depth: 0,
+        };
+        self.write_xml(&mut writer);
+        writer.out
+    }
+
+    fn write_xml(&self, writer: &mut XmlWriter) {
+        let attrs = [("name", self.name.as_deref())];
+        if self.interfaces.is_empty() && self.children.is_empty() {
+            writer.element("node", &attrs, None);
+        } else {
+            writer.element(
+                "node",
+                &attrs,
+                Some(&|w| {
+                    w.list(&self.interfaces, Interface::write_xml);
+                    w.list(&self.children, Node::write_xml);
+                }),
+            );
+        }
+    }
+
     #[cfg(test)]
     pub fn with_name(name: impl Into<String>) -> Node {
         Node {
@@ -90,7 +170,6 @@ impl Interface {
         self.methods.iter().find(|m| m.name == name)
     }
 
-    #[allow(dead_code)]
     pub fn get_signal(&self, name: &str) -> Option<&Signal> {
         self.signals.iter().find(|s| s.name == name)
     }
@@ -99,6 +178,19 @@ impl Interface {
         self.properties.iter().find(|p| p.name == name)
     }
 
+    fn write_xml(&self, writer: &mut XmlWriter) {
+        writer.element(
+            "interface",
+            &[("name", Some(&self.name))],
+            Some(&|w| {
+                w.list(&self.methods, Method::write_xml);
+                w.list(&self.signals, Signal::write_xml);
+                w.list(&self.properties, Property::write_xml);
+                w.list(&self.annotations, Annotation::write_xml);
+            }),
+        );
+    }
+
     /// Represent the interface as a nushell [Value]
     pub fn to_value(&self, span: Span) -> Value {
         Value::record(
@@ -134,7 +226,6 @@ impl Method {
             .collect()
     }
 
-    #[allow(dead_code)]
     /// Get the signature of the method result
     pub fn out_signature(&self) -> String {
         self.args
@@ -144,6 +235,17 @@ impl Method {
             .collect()
     }
 
+    fn write_xml(&self, writer: &mut XmlWriter) {
+        writer.element(
+            "method",
+            &[("name", Some(&self.name))],
+            Some(&|w| {
+                w.list(&self.args, MethodArg::write_xml);
+                w.list(&self.annotations, Annotation::write_xml);
+            }),
+        );
+    }
+
     /// Represent the method as a nushell [Value]
     pub fn to_value(&self, span: Span) -> Value {
         Value::record(
@@ -181,6 +283,22 @@ impl MethodArg {
         }
     }
 
+    fn write_xml(&self, writer: &mut XmlWriter) {
+        let direction = match self.direction {
+            Direction::In => "in",
+            Direction::Out => "out",
+        };
+        writer.element(
+            "arg",
+            &[
+                ("name", self.name.as_deref()),
+                ("type", Some(&self.r#type)),
+                ("direction", Some(direction)),
+            ],
+            None,
+        );
+    }
+
     /// Represent the method as a nushell [Value]
     pub fn to_value(&self, span: Span) -> Value {
         Value::record(
@@ -223,6 +341,17 @@ pub struct Signal {
 }
 
 impl Signal {
+    fn write_xml(&self, writer: &mut XmlWriter) {
+        writer.element(
+            "signal",
+            &[("name", Some(&self.name))],
+            Some(&|w| {
+                w.list(&self.args, SignalArg::write_xml);
+                w.list(&self.annotations, Annotation::write_xml);
+            }),
+        );
+    }
+
     /// Represent the signal as a nushell [Value]
     pub fn to_value(&self, span: Span) -> Value {
         Value::record(
@@ -245,6 +374,14 @@ pub struct SignalArg {
 }
 
 impl SignalArg {
+    fn write_xml(&self, writer: &mut XmlWriter) {
+        writer.element(
+            "arg",
+            &[("name", self.name.as_deref()), ("type", Some(&self.r#type))],
+            None,
+        );
+    }
+
     /// Represent the argument as a nushell [Value]
     pub fn to_value(&self, span: Span) -> Value {
         Value::record(
@@ -268,6 +405,28 @@ pub struct Property {
 }
 
 impl Property {
+    fn write_xml(&self, writer: &mut XmlWriter) {
+        let access = match self.access {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::ReadWrite => "readwrite",
+        };
+        let attrs = [
+            ("name", Some(&self.name[..])),
+            ("type", Some(&self.r#type)),
+            ("access", Some(access)),
+        ];
+        if self.annotations.is_empty() {
+            writer.element("property", &attrs, None);
+        } else {
+            writer.element(
+                "property",
+                &attrs,
+                Some(&|w| w.list(&self.annotations, Annotation::write_xml)),
+            );
+        }
+    }
+
     /// Represent the property as a nushell [Value]
     pub fn to_value(&self, span: Span) -> Value {
         Value::record(
@@ -317,6 +476,14 @@ impl Annotation {
         }
     }
 
+    fn write_xml(&self, writer: &mut XmlWriter) {
+        writer.element(
+            "annotation",
+            &[("name", Some(&self.name)), ("value", Some(&self.value))],
+            None,
+        );
+    }
+
     /// Represent the annotation as a nushell [Value]
     pub fn to_value(&self, span: Span) -> Value {
         Value::record(
@@ -391,6 +558,29 @@ pub fn test_parse_introspection_doc() -> Result<(), serde_xml_rs::Error> {
     Ok(())
 }
 
+#[test]
+pub fn test_to_xml_round_trip() -> Result<(), serde_xml_rs::Error> {
+    let node = test_introspection_doc_rs();
+    let xml = node.to_xml();
+    assert!(xml.contains(r#"<arg name="foo" type="i" direction="in"/>"#));
+    assert_eq!(node, Node::from_xml(&xml)?);
+    Ok(())
+}
+
+#[test]
+pub fn test_to_xml_escape() -> Result<(), serde_xml_rs::Error> {
+    let mut node = Node::with_name("/a");
+    node.interfaces.push(Interface {
+        name: "com.example.Escape".into(),
+        methods: vec![],
+        signals: vec![],
+        properties: vec![],
+        annotations: vec![Annotation::new("com.example.Doc", "<\"it's\" & more>")],
+    });
+    assert_eq!(node, Node::from_xml(&node.to_xml())?);
+    Ok(())
+}
+
 #[test]
 pub fn test_get_method_args_signature() {
     assert_eq!(
@@ -1,20 +1,36 @@
-use nu_plugin::{serve_plugin, MsgPackSerializer, Plugin, PluginCommand};
-use nu_protocol::SyntaxShape;
+use nu_plugin::{serve_plugin, EngineInterface, MsgPackSerializer, Plugin, PluginCommand};
+use nu_protocol::{CustomValue, LabeledError, SyntaxShape};
 
+mod address;
+mod analysis;
+mod auth;
+mod cache;
 mod client;
 mod commands;
 mod config;
+mod connections;
 mod convert;
 mod dbus_type;
 mod introspection;
+mod match_rule;
 mod pattern;
+mod pcap;
+mod server;
 
 fn main() {
-    serve_plugin(&NuPluginDbus, MsgPackSerializer)
+    serve_plugin(&NuPluginDbus::default(), MsgPackSerializer)
 }
 
 /// The main plugin interface for nushell
-pub struct NuPluginDbus;
+#[derive(Default)]
+pub struct NuPluginDbus {
+    /// Connections to each bus, kept open between commands
+    pub connections: connections::Connections,
+    /// Objects being served by `dbus serve`
+    pub servers: std::sync::Arc<server::Servers>,
+    /// Introspection data used to determine signatures
+    pub cache: std::sync::Arc<cache::IntrospectionCache>,
+}
 
 impl Plugin for NuPluginDbus {
     fn version(&self) -> String {
@@ -24,14 +40,51 @@ impl Plugin for NuPluginDbus {
     fn commands(&self) -> Vec<Box<dyn PluginCommand<Plugin = Self>>> {
         vec![
             Box::new(commands::Main),
+            Box::new(commands::Connect),
+            Box::new(commands::ParseAddress),
+            Box::new(commands::Whoami),
             Box::new(commands::Introspect),
             Box::new(commands::Call),
             Box::new(commands::Get),
             Box::new(commands::GetAll),
             Box::new(commands::Set),
+            Box::new(commands::CacheList),
+            Box::new(commands::CacheClear),
             Box::new(commands::List),
+            Box::new(commands::Activate),
+            Box::new(commands::UpdateActivationEnv),
+            Box::new(commands::Stats),
+            Box::new(commands::Monitor),
+            Box::new(commands::ReadCapture),
+            Box::new(commands::Analyze),
+            Box::new(commands::Serve),
+            Box::new(commands::AddObject),
+            Box::new(commands::RemoveObject),
+            Box::new(commands::RequestName),
+            Box::new(commands::ReleaseName),
+            Box::new(commands::Disconnect),
+            Box::new(commands::Emit),
+            Box::new(commands::Listen),
+            Box::new(commands::MatchRule),
+            Box::new(commands::WaitSignal),
+            Box::new(commands::Watch),
+            Box::new(commands::WatchNames),
         ]
     }
+
+    fn custom_value_dropped(
+        &self,
+        engine: &EngineInterface,
+        custom_value: Box<dyn CustomValue>,
+    ) -> Result<(), LabeledError> {
+        if let Some(handle) = custom_value
+            .as_any()
+            .downcast_ref::<connections::ConnectionHandle>()
+        {
+            self.connections.drop_handle(engine, handle.id)?;
+        }
+        Ok(())
+    }
 }
 
 /// For conveniently adding the base options to a dbus command
@@ -39,6 +92,7 @@ trait DbusSignatureUtilExt {
     fn dbus_command(self) -> Self;
     fn accepts_dbus_client_options(self) -> Self;
     fn accepts_timeout(self) -> Self;
+    fn accepts_signal_match_options(self) -> Self;
 }
 
 impl DbusSignatureUtilExt for nu_protocol::Signature {
@@ -67,6 +121,33 @@ impl DbusSignatureUtilExt for nu_protocol::Signature {
                  Will not call the Hello method on initialization.",
                 None,
             )
+            .named(
+                "exec",
+                SyntaxShape::String,
+                "Send to a bus reached through the stdin and stdout of a shell command, \
+                 like `systemd-stdio-bridge`",
+                None,
+            )
+            .named(
+                "auth",
+                SyntaxShape::String,
+                "Authenticate to the --bus or --peer with this mechanism: \
+                 EXTERNAL, ANONYMOUS or DBUS_COOKIE_SHA1",
+                None,
+            )
+            .named(
+                "identity",
+                SyntaxShape::String,
+                "Identity to authenticate as: a uid for EXTERNAL, a user name for \
+                 DBUS_COOKIE_SHA1, or trace information for ANONYMOUS",
+                None,
+            )
+            .named(
+                "connection",
+                SyntaxShape::Any,
+                "Send on a connection opened with `dbus connect`, instead of connecting to a bus",
+                None,
+            )
     }
 
     fn accepts_timeout(self) -> Self {
@@ -77,4 +158,63 @@ impl DbusSignatureUtilExt for nu_protocol::Signature {
             None,
         )
     }
+
+    fn accepts_signal_match_options(self) -> Self {
+        let signature = self
+            .named(
+                "sender",
+                SyntaxShape::String,
+                "Only match signals sent by this connection name",
+                None,
+            )
+            .named(
+                "path",
+                SyntaxShape::String,
+                "Only match signals emitted by the object at this path",
+                None,
+            )
+            .named(
+                "path-namespace",
+                SyntaxShape::String,
+                "Only match signals emitted by objects at or below this path",
+                None,
+            )
+            .named(
+                "interface",
+                SyntaxShape::String,
+                "Only match signals on this interface",
+                None,
+            )
+            .named(
+                "member",
+                SyntaxShape::String,
+                "Only match signals with this name",
+                None,
+            )
+            .named(
+                "arg0namespace",
+                SyntaxShape::String,
+                "Only match signals whose first argument is a bus name or interface within this \
+                 namespace",
+                None,
+            );
+        (0..=match_rule::MAX_ARG_INDEX).fold(signature, |signature, index| {
+            signature
+                .named(
+                    format!("arg{index}"),
+                    SyntaxShape::String,
+                    format!("Only match signals whose argument {index} is this string"),
+                    None,
+                )
+                .named(
+                    format!("arg{index}path"),
+                    SyntaxShape::String,
+                    format!(
+                        "Only match signals whose argument {index} is this path, or a path \
+                         above or below it when either ends in `/`"
+                    ),
+                    None,
+                )
+        })
+    }
 }
@@ -0,0 +1,605 @@
+use std::{collections::BTreeMap, fmt};
+
+use dbus::{arg::ArgType, Message, MessageType};
+use nu_plugin::EvaluatedCall;
+use nu_protocol::{LabeledError, Record, Span, Spanned, Value};
+
+use crate::convert::message_type_name;
+
+/// A D-Bus match rule, as accepted by `AddMatch` and `BecomeMonitor`
+///
+/// A field set to `None` means that the message is not filtered on that field.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MatchRule {
+    pub r#type: Option<MessageType>,
+    pub sender: Option<String>,
+    pub interface: Option<String>,
+    pub member: Option<String>,
+    pub path: Option<String>,
+    pub path_namespace: Option<String>,
+    pub destination: Option<String>,
+    pub args: BTreeMap<u8, ArgCondition>,
+    pub eavesdrop: Option<bool>,
+}
+
+/// A condition on one of the arguments of a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgCondition {
+    /// `argN`: the argument is a string equal to the value
+    Equals(String),
+    /// `argNpath`: the argument is a string or object path equal to the value, or one of them is a
+    /// prefix of the other ending in `/`
+    Path(String),
+    /// `arg0namespace`: the argument is a bus name or interface within the namespace. Only valid
+    /// for the first argument
+    Namespace(String),
+}
+
+/// The highest argument index a match rule can refer to
+pub const MAX_ARG_INDEX: u8 = 63;
+
+impl MatchRule {
+    /// Parse a match rule from its string representation
+    pub fn parse(input: &str) -> Result<MatchRule, String> {
+        let mut rule = MatchRule::default();
+        let mut seen = vec![];
+        let mut chars = input.chars().peekable();
+
+        loop {
+            // Skip whitespace before the key
+            while chars.peek().is_some_and(|ch| ch.is_whitespace()) {
+                chars.next();
+            }
+            if chars.peek().is_none() {
+                break;
+            }
+
+            // The key extends up to the `=`
+            let mut key = String::new();
+            loop {
+                match chars.next() {
+                    Some('=') => break,
+                    Some(ch) if ch.is_whitespace() => {
+                        while chars.peek().is_some_and(|ch| ch.is_whitespace()) {
+                            chars.next();
+                        }
+                        if chars.next() != Some('=') {
+                            return Err(format!("expected `=` after match rule key {key:?}"));
+                        }
+                        break;
+                    }
+                    Some(ch) => key.push(ch),
+                    None => {
+                        return Err(format!(
+                            "unexpected end of match rule after key {key:?}, expected `=`"
+                        ))
+                    }
+                }
+            }
+            if key.is_empty() {
+                return Err("expected a key before `=` in match rule".into());
+            }
+
+            // Like libdbus, skip whitespace between the `=` and the value
+            while chars.peek().is_some_and(|ch| ch.is_whitespace()) {
+                chars.next();
+            }
+
+            // The value extends up to the next unquoted `,`. Apostrophes quote, and can only be
+            // escaped outside of quotes with a backslash
+            let mut value = String::new();
+            let mut quoted = false;
+            loop {
+                match chars.next() {
+                    Some('\'') => quoted = !quoted,
+                    Some('\\') if !quoted && chars.peek() == Some(&'\'') => {
+                        chars.next();
+                        value.push('\'');
+                    }
+                    Some(',') if !quoted => break,
+                    Some(ch) => value.push(ch),
+                    None if quoted => {
+                        return Err(format!(
+                            "unterminated quote in the value of match rule key {key:?}"
+                        ))
+                    }
+                    None => break,
+                }
+            }
+
+            if seen.contains(&key) {
+                return Err(format!("match rule key {key:?} specified more than once"));
+            }
+            rule.set(&key, value)?;
+            seen.push(key);
+        }
+
+        rule.validate()?;
+        Ok(rule)
+    }
+
+    /// Set a key of the match rule, validating the value
+    pub fn set(&mut self, key: &str, value: String) -> Result<(), String> {
+        macro_rules! valid {
+            ($type:ty) => {
+                Some(<$type>::new(value).map(|v| v.to_string())?)
+            };
+        }
+
+        match key {
+            "type" => {
+                self.r#type = Some(MessageType::try_from(&value[..]).map_err(|_| {
+                    format!(
+                        "invalid message type {value:?}, expected one of: \
+                            signal, method_call, method_return, error"
+                    )
+                })?);
+            }
+            "sender" => self.sender = valid!(dbus::strings::BusName),
+            "interface" => self.interface = valid!(dbus::strings::Interface),
+            "member" => self.member = valid!(dbus::strings::Member),
+            "path" => self.path = valid!(dbus::strings::Path),
+            "path_namespace" => self.path_namespace = valid!(dbus::strings::Path),
+            "destination" => self.destination = valid!(dbus::strings::BusName),
+            "eavesdrop" => {
+                self.eavesdrop = Some(match &value[..] {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(format!("eavesdrop must be true or false, got {value:?}")),
+                });
+            }
+            "arg0namespace" => {
+                validate_namespace(&value)?;
+                self.set_arg(0, ArgCondition::Namespace(value))?;
+            }
+            _ => {
+                let Some(arg) = key.strip_prefix("arg") else {
+                    return Err(format!("unknown match rule key {key:?}"));
+                };
+                let (digits, is_path) = match arg.strip_suffix("path") {
+                    Some(digits) => (digits, true),
+                    None => (arg, false),
+                };
+                let index = digits
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|index| {
+                        *index <= MAX_ARG_INDEX && !digits.starts_with('+') && digits.len() <= 2
+                    })
+                    .ok_or_else(|| {
+                        format!(
+                            "unknown match rule key {key:?}, argument index must be \
+                                from 0 to {MAX_ARG_INDEX}"
+                        )
+                    })?;
+                let condition = if is_path {
+                    ArgCondition::Path(value)
+                } else {
+                    ArgCondition::Equals(value)
+                };
+                self.set_arg(index, condition)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_arg(&mut self, index: u8, condition: ArgCondition) -> Result<(), String> {
+        if let Some(existing) = self.args.get(&index) {
+            if std::mem::discriminant(existing) != std::mem::discriminant(&condition) {
+                return Err(format!(
+                    "{} can't be combined with {}",
+                    condition.key(index),
+                    existing.key(index)
+                ));
+            }
+        }
+        self.args.insert(index, condition);
+        Ok(())
+    }
+
+    /// Check the constraints between keys
+    pub fn validate(&self) -> Result<(), String> {
+        if self.path.is_some() && self.path_namespace.is_some() {
+            return Err("path and path_namespace can't be used in the same match rule".into());
+        }
+        Ok(())
+    }
+
+    /// All of the keys and values of the rule, in canonical order
+    fn pairs(&self) -> Vec<(String, String)> {
+        let mut pairs = vec![];
+        if let Some(r#type) = self.r#type {
+            pairs.push(("type".into(), message_type_name(r#type).into()));
+        }
+        for (key, value) in [
+            ("sender", &self.sender),
+            ("interface", &self.interface),
+            ("member", &self.member),
+            ("path", &self.path),
+            ("path_namespace", &self.path_namespace),
+            ("destination", &self.destination),
+        ] {
+            if let Some(value) = value {
+                pairs.push((key.into(), value.clone()));
+            }
+        }
+        for (index, condition) in &self.args {
+            pairs.push((condition.key(*index), condition.value().into()));
+        }
+        if let Some(eavesdrop) = self.eavesdrop {
+            pairs.push(("eavesdrop".into(), eavesdrop.to_string()));
+        }
+        pairs
+    }
+
+    /// Build a rule matching signals from the flags added by
+    /// [accepts_signal_match_options](crate::DbusSignatureUtilExt::accepts_signal_match_options)
+    pub fn from_signal_flags(call: &EvaluatedCall) -> Result<MatchRule, LabeledError> {
+        let mut rule = MatchRule {
+            r#type: Some(MessageType::Signal),
+            ..MatchRule::default()
+        };
+
+        let invalid = |err, span| LabeledError::new("Invalid argument").with_label(err, span);
+
+        for (flag, key) in [
+            ("sender", "sender"),
+            ("path", "path"),
+            ("path-namespace", "path_namespace"),
+            ("interface", "interface"),
+            ("member", "member"),
+        ] {
+            if let Some(value) = call.get_flag::<Spanned<String>>(flag)? {
+                rule.set(key, value.item)
+                    .map_err(|err| invalid(err, value.span))?;
+            }
+        }
+
+        let mut arg_flags = vec!["arg0namespace".to_string()];
+        for index in 0..=MAX_ARG_INDEX {
+            arg_flags.push(format!("arg{index}"));
+            arg_flags.push(format!("arg{index}path"));
+        }
+        for flag in arg_flags {
+            if let Some(value) = call.get_flag::<Spanned<String>>(&flag)? {
+                rule.set(&flag, value.item)
+                    .map_err(|err| invalid(err, value.span))?;
+            }
+        }
+
+        rule.validate().map_err(|err| {
+            invalid(
+                err,
+                call.get_flag_span("path-namespace").unwrap_or(call.head),
+            )
+        })?;
+        Ok(rule)
+    }
+
+    /// Check whether a message matches the rule, locally
+    ///
+    /// The sender is only compared if both it and the rule refer to either unique names or
+    /// well-known names, as the owner of a well-known name can't be known here.
+    pub fn matches(&self, message: &Message) -> bool {
+        if self.r#type.is_some_and(|t| t != message.msg_type()) {
+            return false;
+        }
+
+        if let Some(sender) = &self.sender {
+            if let Some(msg_sender) = message.sender() {
+                if msg_sender.starts_with(':') == sender.starts_with(':') && *msg_sender != **sender
+                {
+                    return false;
+                }
+            }
+        }
+
+        let header_matches = |expected: &Option<String>, actual: Option<&str>| {
+            expected.as_ref().map_or(true, |e| actual == Some(e))
+        };
+        if !header_matches(&self.interface, message.interface().as_deref())
+            || !header_matches(&self.member, message.member().as_deref())
+            || !header_matches(&self.path, message.path().as_deref())
+            || !header_matches(&self.destination, message.destination().as_deref())
+        {
+            return false;
+        }
+
+        if let Some(namespace) = &self.path_namespace {
+            let Some(path) = message.path() else {
+                return false;
+            };
+            if !(namespace == "/"
+                || *path == **namespace
+                || path
+                    .strip_prefix(&namespace[..])
+                    .is_some_and(|rest| rest.starts_with('/')))
+            {
+                return false;
+            }
+        }
+
+        if !self.args.is_empty() {
+            // Collect the arguments that could be matched on, up to the highest index needed
+            let max_index = *self.args.keys().last().unwrap();
+            let mut iter = message.iter_init();
+            let mut arg_values = vec![];
+            for _ in 0..=max_index {
+                arg_values.push(match iter.arg_type() {
+                    ArgType::String => iter.get::<&str>().map(|s| (s.to_owned(), false)),
+                    ArgType::ObjectPath => iter.get::<dbus::Path>().map(|p| (p.to_string(), true)),
+                    _ => None,
+                });
+                iter.next();
+            }
+
+            for (index, condition) in &self.args {
+                let arg = &arg_values[*index as usize];
+                let matched = match (condition, arg) {
+                    (ArgCondition::Equals(expected), Some((actual, false))) => expected == actual,
+                    (ArgCondition::Path(expected), Some((actual, _))) => {
+                        expected == actual
+                            || (expected.ends_with('/') && actual.starts_with(&expected[..]))
+                            || (actual.ends_with('/') && expected.starts_with(&actual[..]))
+                    }
+                    (ArgCondition::Namespace(namespace), Some((actual, false))) => {
+                        actual == namespace
+                            || actual
+                                .strip_prefix(&namespace[..])
+                                .is_some_and(|rest| rest.starts_with('.'))
+                    }
+                    _ => false,
+                };
+                if !matched {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Represent the match rule as a nushell record, with one field per key
+    pub fn to_value(&self, span: Span) -> Value {
+        let mut record = Record::new();
+        for (key, value) in self.pairs() {
+            let value = match &key[..] {
+                "eavesdrop" => Value::bool(value == "true", span),
+                _ => Value::string(value, span),
+            };
+            record.push(key, value);
+        }
+        Value::record(record, span)
+    }
+
+    /// Build a match rule from a nushell record, with one field per key
+    pub fn from_value(value: &Value) -> Result<MatchRule, LabeledError> {
+        let mut rule = MatchRule::default();
+        for (key, field) in value.as_record()? {
+            let field_value = match field {
+                Value::Bool { val, .. } => val.to_string(),
+                Value::Int { val, .. } => val.to_string(),
+                other => other.coerce_string()?,
+            };
+            rule.set(key, field_value).map_err(|err| {
+                LabeledError::new("Invalid match rule").with_label(err, field.span())
+            })?;
+        }
+        rule.validate()
+            .map_err(|err| LabeledError::new("Invalid match rule").with_label(err, value.span()))?;
+        Ok(rule)
+    }
+}
+
+impl fmt::Display for MatchRule {
+    /// Formats the rule as a string suitable for `AddMatch`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (key, value)) in self.pairs().into_iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            // Apostrophes can't be escaped within quotes, so they have to be escaped outside
+            write!(f, "{}='{}'", key, value.replace('\'', r"'\''"))?;
+        }
+        Ok(())
+    }
+}
+
+impl ArgCondition {
+    /// The key used for this condition in a match rule
+    fn key(&self, index: u8) -> String {
+        match self {
+            ArgCondition::Equals(_) => format!("arg{index}"),
+            ArgCondition::Path(_) => format!("arg{index}path"),
+            ArgCondition::Namespace(_) => format!("arg{index}namespace"),
+        }
+    }
+
+    fn value(&self) -> &str {
+        match self {
+            ArgCondition::Equals(v) | ArgCondition::Path(v) | ArgCondition::Namespace(v) => v,
+        }
+    }
+}
+
+/// Check that a namespace consists of valid bus name or interface elements
+fn validate_namespace(namespace: &str) -> Result<(), String> {
+    let valid = !namespace.is_empty()
+        && namespace.split('.').all(|element| {
+            element
+                .chars()
+                .next()
+                .is_some_and(|ch| !ch.is_ascii_digit())
+                && element
+                    .chars()
+                    .all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-')
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("{namespace:?} is not a valid namespace"))
+    }
+}
+
+#[test]
+fn test_parse_empty() {
+    assert_eq!(MatchRule::parse(""), Ok(MatchRule::default()));
+    assert_eq!(MatchRule::parse("  "), Ok(MatchRule::default()));
+}
+
+#[test]
+fn test_parse_headers() {
+    assert_eq!(
+        MatchRule::parse(
+            "type='signal',sender='org.freedesktop.DBus',interface='org.freedesktop.DBus',\
+                member='NameOwnerChanged',path='/org/freedesktop/DBus',destination=':1.42'"
+        ),
+        Ok(MatchRule {
+            r#type: Some(MessageType::Signal),
+            sender: Some("org.freedesktop.DBus".into()),
+            interface: Some("org.freedesktop.DBus".into()),
+            member: Some("NameOwnerChanged".into()),
+            path: Some("/org/freedesktop/DBus".into()),
+            destination: Some(":1.42".into()),
+            ..MatchRule::default()
+        })
+    );
+}
+
+#[test]
+fn test_parse_unquoted_and_whitespace() {
+    assert_eq!(
+        MatchRule::parse(" type ='method_call', member=Ping"),
+        Ok(MatchRule {
+            r#type: Some(MessageType::MethodCall),
+            member: Some("Ping".into()),
+            ..MatchRule::default()
+        })
+    );
+    assert_eq!(
+        MatchRule::parse("type= 'signal', member =  Ping"),
+        Ok(MatchRule {
+            r#type: Some(MessageType::Signal),
+            member: Some("Ping".into()),
+            ..MatchRule::default()
+        })
+    );
+}
+
+#[test]
+fn test_parse_quoting() {
+    let rule = MatchRule::parse(r"arg0='it'\''s, quoted',arg1=don\'t,arg2='a\b'").unwrap();
+    assert_eq!(
+        rule.args.get(&0),
+        Some(&ArgCondition::Equals("it's, quoted".into()))
+    );
+    assert_eq!(
+        rule.args.get(&1),
+        Some(&ArgCondition::Equals("don't".into()))
+    );
+    // Backslashes are literal except before an apostrophe outside of quotes
+    assert_eq!(
+        rule.args.get(&2),
+        Some(&ArgCondition::Equals(r"a\b".into()))
+    );
+}
+
+#[test]
+fn test_parse_args() {
+    let rule = MatchRule::parse("arg0namespace='org.mpris',arg1path='/org/',arg63='x'").unwrap();
+    assert_eq!(
+        rule.args.into_iter().collect::<Vec<_>>(),
+        vec![
+            (0, ArgCondition::Namespace("org.mpris".into())),
+            (1, ArgCondition::Path("/org/".into())),
+            (63, ArgCondition::Equals("x".into())),
+        ]
+    );
+}
+
+#[test]
+fn test_parse_invalid() {
+    assert!(MatchRule::parse("type='sgnal'").is_err());
+    assert!(MatchRule::parse("sender='not a name'").is_err());
+    assert!(MatchRule::parse("path='relative'").is_err());
+    assert!(MatchRule::parse("path='/a',path_namespace='/b'").is_err());
+    assert!(MatchRule::parse("member='A',member='B'").is_err());
+    assert!(MatchRule::parse("arg64='x'").is_err());
+    assert!(MatchRule::parse("arg1namespace='x'").is_err());
+    assert!(MatchRule::parse("arg0='x',arg0namespace='x'").is_err());
+    assert!(MatchRule::parse("arg0namespace='org..mpris'").is_err());
+    assert!(MatchRule::parse("eavesdrop='yes'").is_err());
+    assert!(MatchRule::parse("unknown='x'").is_err());
+    assert!(MatchRule::parse("arg0='unterminated").is_err());
+    assert!(MatchRule::parse("type").is_err());
+    assert!(MatchRule::parse("='x'").is_err());
+}
+
+#[test]
+fn test_to_string_round_trip() {
+    let input = "type='signal',interface='com.example.Foo',path_namespace='/com/example',\
+        arg0='it'\\''s',arg2path='/a/',eavesdrop='true'";
+    let rule = MatchRule::parse(input).unwrap();
+    assert_eq!(rule.to_string(), input);
+    assert_eq!(MatchRule::parse(&rule.to_string()), Ok(rule));
+}
+
+#[test]
+fn test_value_round_trip() {
+    let rule = MatchRule::parse("type='signal',member='Changed',arg1path='/a/'").unwrap();
+    let value = rule.to_value(Span::test_data());
+    assert_eq!(
+        value,
+        Value::test_record(nu_protocol::record!(
+            "type" => Value::test_string("signal"),
+            "member" => Value::test_string("Changed"),
+            "arg1path" => Value::test_string("/a/"),
+        ))
+    );
+    assert_eq!(MatchRule::from_value(&value).unwrap(), rule);
+}
+
+#[cfg(test)]
+fn test_signal(path: &str, member: &str) -> Message {
+    Message::new_signal(path, "com.example.Foo", member).unwrap()
+}
+
+#[test]
+fn test_matches_headers() {
+    let rule = MatchRule::parse("type='signal',interface='com.example.Foo',member='A'").unwrap();
+    assert!(rule.matches(&test_signal("/", "A")));
+    assert!(!rule.matches(&test_signal("/", "B")));
+    let call = Message::new_method_call("com.example", "/", "com.example.Foo", "A").unwrap();
+    assert!(!rule.matches(&call));
+    assert!(MatchRule::default().matches(&call));
+}
+
+#[test]
+fn test_matches_path_namespace() {
+    let rule = MatchRule::parse("path_namespace='/com/example'").unwrap();
+    assert!(rule.matches(&test_signal("/com/example", "A")));
+    assert!(rule.matches(&test_signal("/com/example/child", "A")));
+    assert!(!rule.matches(&test_signal("/com/examples", "A")));
+    assert!(!rule.matches(&test_signal("/com", "A")));
+    let root = MatchRule::parse("path_namespace='/'").unwrap();
+    assert!(root.matches(&test_signal("/com", "A")));
+}
+
+#[test]
+fn test_matches_args() {
+    let message =
+        test_signal("/", "A").append3("org.mpris.MediaPlayer2.foo", "x", dbus::Path::from("/a/b"));
+    let matches = |rule: &str| MatchRule::parse(rule).unwrap().matches(&message);
+    assert!(matches("arg1='x'"));
+    assert!(!matches("arg1='y'"));
+    assert!(!matches("arg3='x'"));
+    assert!(matches("arg0namespace='org.mpris'"));
+    assert!(matches("arg0namespace='org.mpris.MediaPlayer2.foo'"));
+    assert!(!matches("arg0namespace='org.mpr'"));
+    // Object paths only match argNpath
+    assert!(!matches("arg2='/a/b'"));
+    assert!(matches("arg2path='/a/b'"));
+    assert!(matches("arg2path='/a/'"));
+    assert!(matches("arg2path='/'"));
+    assert!(!matches("arg2path='/a'"));
+}
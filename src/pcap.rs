@@ -0,0 +1,311 @@
+use std::io::{self, Read, Write};
+
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use dbus::Message;
+
+/// The link type for D-Bus messages, as used by `busctl capture` and understood by Wireshark
+pub const LINKTYPE_DBUS: u32 = 231;
+
+/// The largest message allowed by the D-Bus specification
+const SNAPLEN: u32 = 128 * 1024 * 1024;
+
+const MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const MAGIC_NANOS: u32 = 0xa1b23c4d;
+
+/// The capture time and raw data of a captured packet
+type Packet = (DateTime<FixedOffset>, Vec<u8>);
+
+/// Writes D-Bus messages to a pcap file
+pub struct PcapWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Start a new capture, writing the pcap file header
+    pub fn new(mut inner: W) -> io::Result<PcapWriter<W>> {
+        let mut header = Vec::with_capacity(24);
+        header.extend(MAGIC_MICROS.to_le_bytes());
+        header.extend(2u16.to_le_bytes()); // version major
+        header.extend(4u16.to_le_bytes()); // version minor
+        header.extend(0i32.to_le_bytes()); // timezone offset, always zero
+        header.extend(0u32.to_le_bytes()); // timestamp accuracy, always zero
+        header.extend(SNAPLEN.to_le_bytes());
+        header.extend(LINKTYPE_DBUS.to_le_bytes());
+        inner.write_all(&header)?;
+        Ok(PcapWriter { inner })
+    }
+
+    /// Write a message to the capture, and flush it so that the file is always complete
+    pub fn write_message<Tz: chrono::TimeZone>(
+        &mut self,
+        message: &Message,
+        timestamp: &DateTime<Tz>,
+    ) -> io::Result<()> {
+        message.marshal(|data| {
+            let len = u32::try_from(data.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "message too large"))?;
+            let mut header = Vec::with_capacity(16);
+            header.extend((timestamp.timestamp() as u32).to_le_bytes());
+            header.extend(timestamp.timestamp_subsec_micros().to_le_bytes());
+            header.extend(len.min(SNAPLEN).to_le_bytes());
+            header.extend(len.to_le_bytes());
+            self.inner.write_all(&header)?;
+            self.inner.write_all(&data[..len.min(SNAPLEN) as usize])
+        })?;
+        self.inner.flush()
+    }
+}
+
+/// Reads captured D-Bus messages from a pcap file
+pub struct PcapReader<R: Read> {
+    inner: R,
+    big_endian: bool,
+    nanos: bool,
+    failed: bool,
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Read the pcap file header, checking that the file contains D-Bus messages
+    pub fn new(mut inner: R) -> Result<PcapReader<R>, String> {
+        let mut header = [0; 24];
+        inner
+            .read_exact(&mut header)
+            .map_err(|err| format!("Failed to read the pcap file header: {err}"))?;
+
+        let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let (big_endian, nanos) = match (magic, magic.swap_bytes()) {
+            (MAGIC_MICROS, _) => (false, false),
+            (MAGIC_NANOS, _) => (false, true),
+            (_, MAGIC_MICROS) => (true, false),
+            (_, MAGIC_NANOS) => (true, true),
+            _ => return Err("Not a pcap file (pcapng is not supported)".into()),
+        };
+
+        let reader = PcapReader {
+            inner,
+            big_endian,
+            nanos,
+            failed: false,
+        };
+        let linktype = reader.u32_at(&header, 20);
+        if linktype & 0xffff != LINKTYPE_DBUS {
+            return Err(format!(
+                "The capture has link type {linktype}, but only D-Bus ({LINKTYPE_DBUS}) is \
+                    supported"
+            ));
+        }
+        Ok(reader)
+    }
+
+    fn u32_at(&self, bytes: &[u8], offset: usize) -> u32 {
+        let bytes = bytes[offset..offset + 4].try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    fn read_packet(&mut self) -> Result<Option<Packet>, String> {
+        let mut header = [0; 16];
+        let mut filled = 0;
+        while filled < header.len() {
+            match self.inner.read(&mut header[filled..]) {
+                // Only the end of the file between packets is the end of the capture
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err("Capture ended in the middle of a packet".into()),
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(format!("Failed to read from the capture: {err}")),
+            }
+        }
+
+        let secs = self.u32_at(&header, 0);
+        let frac = self.u32_at(&header, 4);
+        let included_len = self.u32_at(&header, 8);
+        let original_len = self.u32_at(&header, 12);
+
+        if included_len > SNAPLEN {
+            return Err(format!(
+                "Captured packet is too large ({included_len} bytes)"
+            ));
+        }
+        let mut data = vec![0; included_len as usize];
+        self.inner
+            .read_exact(&mut data)
+            .map_err(|err| format!("Capture ended in the middle of a packet: {err}"))?;
+        if included_len < original_len {
+            return Err(format!(
+                "Captured packet was truncated from {original_len} to {included_len} bytes"
+            ));
+        }
+
+        let nanos = if self.nanos {
+            Some(frac)
+        } else {
+            frac.checked_mul(1000)
+        };
+        let timestamp = nanos
+            .and_then(|nanos| DateTime::<Utc>::from_timestamp(secs.into(), nanos))
+            .ok_or_else(|| format!("Invalid timestamp in the capture: {secs}.{frac}"))?;
+        Ok(Some((timestamp.with_timezone(&Local).fixed_offset(), data)))
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = Result<(DateTime<FixedOffset>, Message), String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        // A broken file can't be recovered from, but a single message that doesn't decode can be
+        // skipped over
+        let (timestamp, data) = match self.read_packet().transpose()? {
+            Ok(packet) => packet,
+            Err(err) => {
+                self.failed = true;
+                return Some(Err(err));
+            }
+        };
+        Some(
+            Message::demarshal(&data)
+                .map(|message| (timestamp, message))
+                .map_err(|err| {
+                    format!(
+                        "Failed to decode a captured message: {}",
+                        err.message().unwrap_or("unknown error")
+                    )
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+fn test_message(serial: u32) -> Message {
+    let mut message = Message::new_signal("/org/example", "org.example.Test", "Ping")
+        .unwrap()
+        .append2("hello", serial);
+    message.set_serial(serial);
+    message
+}
+
+#[test]
+fn test_round_trip() {
+    let timestamp = DateTime::from_timestamp(1700000000, 123_456_000).unwrap();
+    let mut buf = vec![];
+    let mut writer = PcapWriter::new(&mut buf).unwrap();
+    writer.write_message(&test_message(1), &timestamp).unwrap();
+    writer.write_message(&test_message(2), &timestamp).unwrap();
+
+    let messages = PcapReader::new(&buf[..])
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(2, messages.len());
+    for (index, (read_timestamp, message)) in messages.iter().enumerate() {
+        assert_eq!(timestamp, *read_timestamp);
+        assert_eq!("/org/example", &*message.path().unwrap());
+        assert_eq!("Ping", &*message.member().unwrap());
+        assert_eq!(
+            ("hello", index as u32 + 1),
+            message.read2::<&str, u32>().unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_header_format() {
+    let mut buf = vec![];
+    PcapWriter::new(&mut buf).unwrap();
+    assert_eq!(24, buf.len());
+    assert_eq!([0xd4, 0xc3, 0xb2, 0xa1], buf[0..4]);
+    assert_eq!(LINKTYPE_DBUS.to_le_bytes(), buf[20..24]);
+}
+
+#[test]
+fn test_big_endian_nanos() {
+    let mut data = vec![];
+    data.extend(MAGIC_NANOS.to_be_bytes());
+    data.extend(2u16.to_be_bytes());
+    data.extend(4u16.to_be_bytes());
+    data.extend([0; 8]);
+    data.extend(SNAPLEN.to_be_bytes());
+    data.extend(LINKTYPE_DBUS.to_be_bytes());
+    test_message(7)
+        .marshal::<(), _>(|message| {
+            data.extend(1700000000u32.to_be_bytes());
+            data.extend(5u32.to_be_bytes());
+            data.extend((message.len() as u32).to_be_bytes());
+            data.extend((message.len() as u32).to_be_bytes());
+            data.extend(message);
+            Ok(())
+        })
+        .unwrap();
+
+    let (timestamp, message) = PcapReader::new(&data[..]).unwrap().next().unwrap().unwrap();
+    assert_eq!(5, timestamp.timestamp_subsec_nanos());
+    assert_eq!(Some(7), message.get_serial());
+}
+
+#[test]
+fn test_wrong_linktype() {
+    let mut data = vec![];
+    data.extend(MAGIC_MICROS.to_le_bytes());
+    data.extend([2, 0, 4, 0]);
+    data.extend([0; 8]);
+    data.extend(SNAPLEN.to_le_bytes());
+    data.extend(1u32.to_le_bytes()); // ethernet
+    assert!(PcapReader::new(&data[..]).is_err());
+}
+
+#[test]
+fn test_truncated_file() {
+    let mut buf = vec![];
+    let mut writer = PcapWriter::new(&mut buf).unwrap();
+    writer
+        .write_message(&test_message(1), &DateTime::UNIX_EPOCH)
+        .unwrap();
+    buf.truncate(buf.len() - 4);
+
+    let results = PcapReader::new(&buf[..]).unwrap().collect::<Vec<_>>();
+    assert_eq!(1, results.len());
+    assert!(results[0].is_err());
+}
+
+#[test]
+fn test_invalid_timestamp() {
+    let mut data = vec![];
+    data.extend(MAGIC_MICROS.to_le_bytes());
+    data.extend([2, 0, 4, 0]);
+    data.extend([0; 8]);
+    data.extend(SNAPLEN.to_le_bytes());
+    data.extend(LINKTYPE_DBUS.to_le_bytes());
+    // More microseconds than fit in nanoseconds
+    data.extend(0u32.to_le_bytes());
+    data.extend(u32::MAX.to_le_bytes());
+    data.extend(0u32.to_le_bytes());
+    data.extend(0u32.to_le_bytes());
+
+    let results = PcapReader::new(&data[..]).unwrap().collect::<Vec<_>>();
+    assert_eq!(1, results.len());
+    assert!(results[0]
+        .as_ref()
+        .unwrap_err()
+        .contains("Invalid timestamp"));
+}
+
+#[test]
+fn test_truncated_header() {
+    let mut buf = vec![];
+    let mut writer = PcapWriter::new(&mut buf).unwrap();
+    writer
+        .write_message(&test_message(1), &DateTime::UNIX_EPOCH)
+        .unwrap();
+    // Half of the packet header, after the 24 bytes of the file header
+    buf.truncate(24 + 8);
+
+    let results = PcapReader::new(&buf[..]).unwrap().collect::<Vec<_>>();
+    assert_eq!(1, results.len());
+    assert!(results[0].is_err());
+}
@@ -0,0 +1,1190 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::CString,
+    sync::{Arc, Mutex},
+};
+
+use dbus::{
+    arg::{
+        messageitem::{MessageItem, MessageItemArray, MessageItemDict},
+        RefArg, Variant,
+    },
+    channel::{default_reply, Channel},
+    strings::ErrorName,
+    Message,
+};
+use nu_plugin::EngineInterface;
+use nu_protocol::{engine::Closure, record, LabeledError, Span, Spanned, Value};
+
+use crate::{
+    config::DbusBusChoice,
+    convert,
+    dbus_type::DbusType,
+    introspection::{
+        Access, Direction, Interface, Method, MethodArg, Node, Property, Signal, SignalArg,
+    },
+};
+
+const INTROSPECTABLE: &str = "org.freedesktop.DBus.Introspectable";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+const PEER: &str = "org.freedesktop.DBus.Peer";
+const OBJECT_MANAGER: &str = "org.freedesktop.DBus.ObjectManager";
+
+/// The interfaces implemented for every served object
+const STANDARD_INTERFACES: &str = r#"<node>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml_data" type="s" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get">
+      <arg name="interface_name" type="s" direction="in"/>
+      <arg name="property_name" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="GetAll">
+      <arg name="interface_name" type="s" direction="in"/>
+      <arg name="props" type="a{sv}" direction="out"/>
+    </method>
+    <method name="Set">
+      <arg name="interface_name" type="s" direction="in"/>
+      <arg name="property_name" type="s" direction="in"/>
+      <arg name="value" type="v" direction="in"/>
+    </method>
+    <signal name="PropertiesChanged">
+      <arg name="interface_name" type="s"/>
+      <arg name="changed_properties" type="a{sv}"/>
+      <arg name="invalidated_properties" type="as"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+    <method name="GetMachineId">
+      <arg name="machine_uuid" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>"#;
+
+/// The interface implemented at the root of the tree, if it has an object manager
+const OBJECT_MANAGER_INTERFACE: &str = r#"<node>
+  <interface name="org.freedesktop.DBus.ObjectManager">
+    <method name="GetManagedObjects">
+      <arg name="objects" type="a{oa{sa{sv}}}" direction="out"/>
+    </method>
+    <signal name="InterfacesAdded">
+      <arg name="object" type="o"/>
+      <arg name="interfaces" type="a{sa{sv}}"/>
+    </signal>
+    <signal name="InterfacesRemoved">
+      <arg name="object" type="o"/>
+      <arg name="interfaces" type="as"/>
+    </signal>
+  </interface>
+</node>"#;
+
+/// The object trees exported by running `dbus serve` commands, by the bus they're served on
+#[derive(Default)]
+pub struct Servers {
+    servers: Mutex<HashMap<DbusBusChoice, Server>>,
+}
+
+/// An object tree being served, and the connection it's served on
+#[derive(Clone)]
+pub struct Server {
+    pub tree: Arc<Mutex<ObjectTree>>,
+    pub channel: Arc<Channel>,
+}
+
+/// Unregisters a server when dropped
+pub struct ServerGuard {
+    servers: Arc<Servers>,
+    bus: DbusBusChoice,
+}
+
+impl Servers {
+    /// Register a tree being served on a bus, until the returned guard is dropped
+    pub fn register(
+        self: &Arc<Self>,
+        bus: &Spanned<DbusBusChoice>,
+        server: Server,
+    ) -> Result<ServerGuard, LabeledError> {
+        let mut servers = self.servers.lock().unwrap_or_else(|err| err.into_inner());
+        if servers.contains_key(&bus.item) {
+            return Err(LabeledError::new("Already serving objects on this bus")
+                .with_label("stop the other `dbus serve` first", bus.span));
+        }
+        servers.insert(bus.item.clone(), server);
+        Ok(ServerGuard {
+            servers: self.clone(),
+            bus: bus.item.clone(),
+        })
+    }
+
+    /// Get the server on a bus, if there is one
+    pub fn get(&self, bus: &DbusBusChoice) -> Option<Server> {
+        let servers = self.servers.lock().unwrap_or_else(|err| err.into_inner());
+        servers.get(bus).cloned()
+    }
+}
+
+impl Drop for ServerGuard {
+    fn drop(&mut self) {
+        let mut servers = self
+            .servers
+            .servers
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        servers.remove(&self.bus);
+    }
+}
+
+/// Objects exported on the bus from nushell, with their methods implemented by closures
+#[derive(Debug, Clone, Default)]
+pub struct ObjectTree {
+    objects: BTreeMap<String, Vec<ServedInterface>>,
+    /// The path where `org.freedesktop.DBus.ObjectManager` is implemented, if anywhere
+    object_manager: Option<String>,
+}
+
+/// An interface on an exported object
+#[derive(Debug, Clone)]
+struct ServedInterface {
+    /// The description of the interface, as it would be introspected
+    spec: Interface,
+    /// The closures that implement each of the methods
+    closures: BTreeMap<String, Spanned<Closure>>,
+    /// The current values of the properties
+    values: BTreeMap<String, Value>,
+}
+
+/// An error to send back to the caller of a method
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MethodError {
+    pub name: String,
+    pub message: String,
+}
+
+impl MethodError {
+    fn new(name: &str, message: impl Into<String>) -> MethodError {
+        MethodError {
+            name: format!("org.freedesktop.DBus.Error.{name}"),
+            message: message.into(),
+        }
+    }
+
+    /// Make the error reply to a method call
+    pub fn reply_to(&self, call: &Message) -> Message {
+        let name = ErrorName::new(&self.name)
+            .unwrap_or_else(|_| "org.freedesktop.DBus.Error.Failed".into());
+        let message = CString::new(self.message.replace('\0', "")).unwrap_or_default();
+        call.error(&name, &message)
+    }
+
+    /// Represent the error as a nushell [Value]
+    pub fn to_value(&self, span: Span) -> Value {
+        Value::record(
+            record! {
+                "name" => Value::string(&self.name, span),
+                "message" => Value::string(&self.message, span),
+            },
+            span,
+        )
+    }
+}
+
+impl ObjectTree {
+    /// Parse a description of the objects to serve, in the form
+    /// `{ <path>: { <interface>: { methods: { <name>: { in, out, closure } },
+    /// properties: { <name>: { type, access, value } }, signals: { <name>: <args> } } } }`
+    pub fn from_value(value: &Value) -> Result<ObjectTree, LabeledError> {
+        let mut tree = ObjectTree::default();
+        for (path, interfaces) in value.as_record()? {
+            tree.objects
+                .insert(path.clone(), parse_object(path, interfaces)?);
+        }
+        Ok(tree)
+    }
+
+    /// Implement `org.freedesktop.DBus.ObjectManager` at `root`, for the objects below it
+    pub fn set_object_manager(&mut self, root: &Spanned<String>) -> Result<(), LabeledError> {
+        dbus::strings::Path::new(&root.item)
+            .map_err(|err| LabeledError::new("Invalid object path").with_label(err, root.span))?;
+        self.object_manager = Some(root.item.clone());
+        Ok(())
+    }
+
+    /// Export another object, returning the `InterfacesAdded` signal to emit if it's managed
+    pub fn add_object(
+        &mut self,
+        path: &Spanned<String>,
+        interfaces: &Value,
+    ) -> Result<Option<Message>, LabeledError> {
+        if self.objects.contains_key(&path.item) {
+            return Err(LabeledError::new("Object already exists")
+                .with_label("remove this object first", path.span));
+        }
+        let interfaces = parse_object(&path.item, interfaces)?;
+        let signal = self
+            .manager_of(&path.item)
+            .map(|root| {
+                let mut signal = Message::new_signal(root, OBJECT_MANAGER, "InterfacesAdded")?;
+                signal.append_items(&[
+                    MessageItem::ObjectPath(path.item.clone().into()),
+                    interfaces_and_properties(&interfaces)?,
+                ]);
+                Ok(signal)
+            })
+            .transpose()
+            .map_err(|err: String| LabeledError::new(err).with_label("adding this", path.span))?;
+        self.objects.insert(path.item.clone(), interfaces);
+        Ok(signal)
+    }
+
+    /// Stop exporting an object, returning the `InterfacesRemoved` signal to emit if it was
+    /// managed
+    pub fn remove_object(
+        &mut self,
+        path: &Spanned<String>,
+    ) -> Result<Option<Message>, LabeledError> {
+        let interfaces = self.objects.remove(&path.item).ok_or_else(|| {
+            LabeledError::new("No such object").with_label("this isn't being served", path.span)
+        })?;
+        self.manager_of(&path.item)
+            .map(|root| {
+                let names = interfaces
+                    .iter()
+                    .map(|served| MessageItem::Str(served.spec.name.clone()))
+                    .collect();
+                let names =
+                    MessageItemArray::new(names, "as".into()).map_err(|err| format!("{err:?}"))?;
+                let mut signal = Message::new_signal(root, OBJECT_MANAGER, "InterfacesRemoved")?;
+                signal.append_items(&[
+                    MessageItem::ObjectPath(path.item.clone().into()),
+                    MessageItem::Array(names),
+                ]);
+                Ok(signal)
+            })
+            .transpose()
+            .map_err(|err: String| LabeledError::new(err).with_label("removing this", path.span))
+    }
+
+    /// The root of the object manager, if an object at `path` is managed by it
+    fn manager_of(&self, path: &str) -> Option<&str> {
+        self.object_manager
+            .as_deref()
+            .filter(|root| is_below(path, root))
+    }
+
+    /// The names of the nodes directly below `path` that lead to exported objects
+    fn children(&self, path: &str) -> Vec<String> {
+        let mut children = self
+            .objects
+            .keys()
+            .filter(|object| is_below(object, path))
+            .filter_map(|object| {
+                let rest = object[path.len()..].trim_start_matches('/');
+                rest.split('/').next().map(String::from)
+            })
+            .collect::<Vec<_>>();
+        children.dedup();
+        children
+    }
+
+    /// The objects below the object manager, with their interfaces and properties, as returned by
+    /// `GetManagedObjects`
+    fn managed_objects(&self, root: &str) -> Result<MessageItem, MethodError> {
+        let objects = self
+            .objects
+            .iter()
+            .filter(|(path, _)| is_below(path, root))
+            .map(|(path, interfaces)| {
+                Ok((
+                    MessageItem::ObjectPath(path.clone().into()),
+                    interfaces_and_properties(interfaces)
+                        .map_err(|err| MethodError::new("Failed", err))?,
+                ))
+            })
+            .collect::<Result<Vec<_>, MethodError>>()?;
+        MessageItemDict::new(objects, "o".into(), "a{sa{sv}}".into())
+            .map(MessageItem::Dict)
+            .map_err(|err| MethodError::new("Failed", format!("{err:?}")))
+    }
+
+    /// Find the signature of a signal declared on an exported object
+    pub fn signal_signature(&self, path: &str, interface: &str, signal: &str) -> Option<String> {
+        let served = self
+            .objects
+            .get(path)?
+            .iter()
+            .find(|served| served.spec.name == interface)?;
+        let signal = served.spec.get_signal(signal)?;
+        Some(signal.args.iter().map(|arg| &arg.r#type[..]).collect())
+    }
+
+    /// Decide how to handle a method call: by implementing one of the standard interfaces
+    /// straight away, or by running the closure for the method
+    ///
+    /// Returns the error to reply with if the call can't be handled
+    pub fn dispatch(&mut self, call: &Message, span: Span) -> Result<Dispatch, MethodError> {
+        let (Some(path), Some(member)) = (call.path(), call.member()) else {
+            return Err(MethodError::new(
+                "InvalidArgs",
+                "Method call has no path or member",
+            ));
+        };
+        let interface = call.interface();
+
+        if interface.as_deref() == Some(PEER) {
+            if let Some(reply) = default_reply(call) {
+                return Ok(Dispatch::Reply(reply, vec![]));
+            }
+        }
+
+        let is_manager = self.object_manager.as_deref() == Some(&*path);
+        if is_manager && interface.as_deref() == Some(OBJECT_MANAGER) {
+            return match &*member {
+                "GetManagedObjects" => Ok(Dispatch::Reply(
+                    call.method_return().append1(self.managed_objects(&path)?),
+                    vec![],
+                )),
+                _ => Err(MethodError::new(
+                    "UnknownMethod",
+                    format!("No such method {member} on {OBJECT_MANAGER}"),
+                )),
+            };
+        }
+
+        // Paths above exported objects can be introspected to find them
+        let children = self.children(&path);
+        let interfaces = match self.objects.get_mut(&*path) {
+            Some(interfaces) => &mut interfaces[..],
+            None if is_manager || !children.is_empty() => &mut [],
+            None => {
+                return Err(MethodError::new(
+                    "UnknownObject",
+                    format!("No such object: {path}"),
+                ))
+            }
+        };
+
+        match (interface.as_deref(), &*member) {
+            (Some(INTROSPECTABLE), "Introspect") => Ok(Dispatch::Reply(
+                call.method_return()
+                    .append1(introspect(interfaces, &children, is_manager)?),
+                vec![],
+            )),
+            (Some(PROPERTIES), _) => handle_properties(interfaces, call, &member, span)
+                .map(|(reply, signals)| Dispatch::Reply(reply, signals)),
+            _ => {
+                let served = find_interface(interfaces, interface.as_deref(), &member)?;
+                served.closure_call(call, &member).map(Dispatch::Closure)
+            }
+        }
+    }
+}
+
+/// Parse the description of the interfaces of an object
+fn parse_object(path: &str, interfaces: &Value) -> Result<Vec<ServedInterface>, LabeledError> {
+    dbus::strings::Path::new(path).map_err(|err| {
+        LabeledError::new("Invalid object path").with_label(err, interfaces.span())
+    })?;
+    interfaces
+        .as_record()?
+        .iter()
+        .map(|(name, spec)| ServedInterface::from_value(name, spec))
+        .collect()
+}
+
+/// Check if `path` is strictly below `parent` in the object tree
+fn is_below(path: &str, parent: &str) -> bool {
+    path.strip_prefix(parent)
+        .is_some_and(|rest| !rest.is_empty() && (parent.ends_with('/') || rest.starts_with('/')))
+}
+
+/// Generate the introspection XML for an object, with the names of the nodes below it
+fn introspect(
+    interfaces: &[ServedInterface],
+    children: &[String],
+    object_manager: bool,
+) -> Result<String, MethodError> {
+    let parse =
+        |xml| Node::from_xml(xml).map_err(|err| MethodError::new("Failed", err.to_string()));
+    let mut node = parse(STANDARD_INTERFACES)?;
+    if object_manager {
+        node.interfaces
+            .extend(parse(OBJECT_MANAGER_INTERFACE)?.interfaces);
+    }
+    node.interfaces
+        .splice(0..0, interfaces.iter().map(|served| served.spec.clone()));
+    node.children = children
+        .iter()
+        .map(|name| Node {
+            name: Some(name.clone()),
+            ..Node::default()
+        })
+        .collect();
+    Ok(node.to_xml())
+}
+
+/// Encode the interfaces of an object with their readable properties, as an `a{sa{sv}}`
+fn interfaces_and_properties(interfaces: &[ServedInterface]) -> Result<MessageItem, String> {
+    let interfaces = interfaces
+        .iter()
+        .map(|served| {
+            let properties = served.get_all().map_err(|err| err.message)?;
+            Ok((MessageItem::Str(served.spec.name.clone()), properties))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    MessageItemDict::new(interfaces, "s".into(), "a{sv}".into())
+        .map(MessageItem::Dict)
+        .map_err(|err| format!("{err:?}"))
+}
+
+/// Find the interface a method call is for. The interface is optional in method calls, in which
+/// case any method with the name is called
+fn find_interface<'a>(
+    interfaces: &'a mut [ServedInterface],
+    interface: Option<&str>,
+    member: &str,
+) -> Result<&'a mut ServedInterface, MethodError> {
+    match interface {
+        Some(interface) => interfaces
+            .iter_mut()
+            .find(|served| served.spec.name == interface)
+            .ok_or_else(|| {
+                MethodError::new("UnknownInterface", format!("No such interface {interface}"))
+            }),
+        None => interfaces
+            .iter_mut()
+            .find(|served| served.spec.get_method(member).is_some())
+            .ok_or_else(|| MethodError::new("UnknownMethod", format!("No such method {member}"))),
+    }
+}
+
+/// Implement `org.freedesktop.DBus.Properties` for an object
+fn handle_properties(
+    interfaces: &mut [ServedInterface],
+    call: &Message,
+    member: &str,
+    span: Span,
+) -> Result<(Message, Vec<Message>), MethodError> {
+    let invalid_args =
+        |err: dbus::arg::TypeMismatchError| MethodError::new("InvalidArgs", err.to_string());
+    match member {
+        "Get" => {
+            let (interface, name) = call.read2::<&str, &str>().map_err(invalid_args)?;
+            let served = find_interface(interfaces, Some(interface), member)?;
+            let value = served.get_property(name)?;
+            Ok((call.method_return().append1(value), vec![]))
+        }
+        "GetAll" => {
+            let interface = call.read1::<&str>().map_err(invalid_args)?;
+            let served = find_interface(interfaces, Some(interface), member)?;
+            Ok((call.method_return().append1(served.get_all()?), vec![]))
+        }
+        "Set" => {
+            let (interface, name, Variant(value)) = call
+                .read3::<&str, &str, Variant<Box<dyn RefArg>>>()
+                .map_err(invalid_args)?;
+            let served = find_interface(interfaces, Some(interface), member)?;
+            let changed = served.set_property(call, name, &*value, span)?;
+            Ok((call.method_return(), vec![changed]))
+        }
+        _ => Err(MethodError::new(
+            "UnknownMethod",
+            format!("No such method {member} on {PROPERTIES}"),
+        )),
+    }
+}
+
+/// What to do in response to a method call
+pub enum Dispatch {
+    /// Send the reply, followed by the signals
+    Reply(Message, Vec<Message>),
+    /// Run a closure to get the reply
+    Closure(ClosureCall),
+}
+
+/// A method call to be handled by a closure. This doesn't borrow the tree, so that the closure
+/// can use other commands that need it
+pub struct ClosureCall {
+    interface: String,
+    method: Method,
+    closure: Spanned<Closure>,
+}
+
+impl ClosureCall {
+    /// Run the closure with the arguments of the call, and make the reply from its result
+    pub fn run(
+        &self,
+        engine: &EngineInterface,
+        call: &Message,
+        span: Span,
+    ) -> Result<Message, MethodError> {
+        let args = convert::from_message(call, span)
+            .map_err(|err| MethodError::new("InvalidArgs", err))?;
+        let string_or_nothing =
+            |s: Option<&str>| s.map(|s| Value::string(s, span)).unwrap_or_default();
+        let input = Value::record(
+            record! {
+                "sender" => string_or_nothing(call.sender().as_deref()),
+                "path" => string_or_nothing(call.path().as_deref()),
+                "interface" => Value::string(&self.interface, span),
+                "member" => Value::string(&self.method.name, span),
+            },
+            span,
+        );
+        let result = engine
+            .eval_closure(&self.closure, args, Some(input))
+            .map_err(|err| MethodError::new("Failed", LabeledError::from(err).msg))?;
+
+        let mut reply = call.method_return();
+        reply.append_items(&encode_reply(&self.method, result)?);
+        Ok(reply)
+    }
+}
+
+impl ServedInterface {
+    fn from_value(name: &str, spec: &Value) -> Result<ServedInterface, LabeledError> {
+        dbus::strings::Interface::new(name).map_err(|err| {
+            LabeledError::new("Invalid interface name").with_label(err, spec.span())
+        })?;
+
+        let mut served = ServedInterface {
+            spec: Interface {
+                name: name.into(),
+                methods: vec![],
+                signals: vec![],
+                properties: vec![],
+                annotations: vec![],
+            },
+            closures: BTreeMap::new(),
+            values: BTreeMap::new(),
+        };
+
+        for (key, value) in spec.as_record()? {
+            match &key[..] {
+                "methods" => {
+                    for (name, method) in value.as_record()? {
+                        served.add_method(name, method)?;
+                    }
+                }
+                "properties" => {
+                    for (name, property) in value.as_record()? {
+                        served.add_property(name, property)?;
+                    }
+                }
+                "signals" => {
+                    for (name, args) in value.as_record()? {
+                        served.add_signal(name, args)?;
+                    }
+                }
+                _ => {
+                    return Err(LabeledError::new(format!(
+                        "Unknown key {key:?} in the description of {name}"
+                    ))
+                    .with_label(
+                        "expected `methods`, `properties`, or `signals`",
+                        value.span(),
+                    ))
+                }
+            }
+        }
+        Ok(served)
+    }
+
+    /// Find the closure to run for a method call, checking the arguments
+    fn closure_call(&self, call: &Message, member: &str) -> Result<ClosureCall, MethodError> {
+        let (Some(method), Some(closure)) =
+            (self.spec.get_method(member), self.closures.get(member))
+        else {
+            return Err(MethodError::new(
+                "UnknownMethod",
+                format!("No such method {member} on {}", self.spec.name),
+            ));
+        };
+
+        let signature = convert::message_signature(call);
+        if signature != method.in_signature() {
+            return Err(MethodError::new(
+                "InvalidArgs",
+                format!(
+                    "Expected arguments of type {:?}, got {signature:?}",
+                    method.in_signature()
+                ),
+            ));
+        }
+
+        Ok(ClosureCall {
+            interface: self.spec.name.clone(),
+            method: method.clone(),
+            closure: closure.clone(),
+        })
+    }
+
+    /// Get the value of a property, as a variant
+    fn get_property(&self, name: &str) -> Result<MessageItem, MethodError> {
+        let (property, value) = self.find_property(name)?;
+        if property.access == Access::Write {
+            return Err(MethodError::new(
+                "AccessDenied",
+                format!("Property {name} is write-only"),
+            ));
+        }
+        let value = value
+            .ok_or_else(|| MethodError::new("Failed", format!("Property {name} has no value")))?;
+        encode_property(property, value)
+    }
+
+    /// Get the values of all readable properties, as an `a{sv}`
+    fn get_all(&self) -> Result<MessageItem, MethodError> {
+        let properties = self
+            .spec
+            .properties
+            .iter()
+            .filter(|property| property.access != Access::Write)
+            .map(|property| {
+                let value = self.get_property(&property.name)?;
+                Ok((MessageItem::Str(property.name.clone()), value))
+            })
+            .collect::<Result<Vec<_>, MethodError>>()?;
+        MessageItemDict::new(properties, "s".into(), "v".into())
+            .map(MessageItem::Dict)
+            .map_err(|err| MethodError::new("Failed", format!("{err:?}")))
+    }
+
+    /// Set the value of a property, returning the `PropertiesChanged` signal to emit
+    fn set_property(
+        &mut self,
+        call: &Message,
+        name: &str,
+        value: &dyn RefArg,
+        span: Span,
+    ) -> Result<Message, MethodError> {
+        let (property, _) = self.find_property(name)?;
+        if property.access == Access::Read {
+            return Err(MethodError::new(
+                "PropertyReadOnly",
+                format!("Property {name} is read-only"),
+            ));
+        }
+        if *value.signature() != property.r#type {
+            return Err(MethodError::new(
+                "InvalidArgs",
+                format!(
+                    "Property {name} has type {:?}, got {:?}",
+                    property.r#type,
+                    &*value.signature()
+                ),
+            ));
+        }
+        let value = convert::from_refarg(value, span)
+            .map_err(|err| MethodError::new("InvalidArgs", err))?;
+
+        let changed = MessageItemDict::new(
+            vec![(
+                MessageItem::Str(name.into()),
+                encode_property(property, &value)?,
+            )],
+            "s".into(),
+            "v".into(),
+        )
+        .map_err(|err| MethodError::new("Failed", format!("{err:?}")))?;
+        let invalidated = MessageItemArray::new(vec![], "as".into())
+            .map_err(|err| MethodError::new("Failed", format!("{err:?}")))?;
+        let path = call
+            .path()
+            .ok_or_else(|| MethodError::new("InvalidArgs", "Method call has no path"))?;
+        let mut signal = Message::new_signal(&*path, PROPERTIES, "PropertiesChanged")
+            .map_err(|err| MethodError::new("Failed", err))?;
+        signal.append_items(&[
+            MessageItem::Str(self.spec.name.clone()),
+            MessageItem::Dict(changed),
+            MessageItem::Array(invalidated),
+        ]);
+
+        self.values.insert(name.into(), value);
+        Ok(signal)
+    }
+
+    fn find_property(&self, name: &str) -> Result<(&Property, Option<&Value>), MethodError> {
+        let property = self.spec.get_property(name).ok_or_else(|| {
+            MethodError::new(
+                "UnknownProperty",
+                format!("No such property {name} on {}", self.spec.name),
+            )
+        })?;
+        Ok((property, self.values.get(name)))
+    }
+
+    fn add_method(&mut self, name: &str, spec: &Value) -> Result<(), LabeledError> {
+        dbus::strings::Member::new(name)
+            .map_err(|err| LabeledError::new("Invalid method name").with_label(err, spec.span()))?;
+
+        let mut method = Method {
+            name: name.into(),
+            args: vec![],
+            annotations: vec![],
+        };
+        let mut closure = None;
+        for (key, value) in spec.as_record()? {
+            match &key[..] {
+                "in" => method.args.extend(parse_args(value, Direction::In)?),
+                "out" => method.args.extend(parse_args(value, Direction::Out)?),
+                "closure" => {
+                    closure = Some(Spanned {
+                        item: value.as_closure()?.clone(),
+                        span: value.span(),
+                    })
+                }
+                _ => {
+                    return Err(LabeledError::new(format!(
+                        "Unknown key {key:?} in the description of method {name}"
+                    ))
+                    .with_label("expected `in`, `out`, or `closure`", value.span()))
+                }
+            }
+        }
+
+        let closure = closure.ok_or_else(|| {
+            LabeledError::new(format!("Method {name} has no closure"))
+                .with_label("add a `closure` to run when this is called", spec.span())
+        })?;
+        self.spec.methods.push(method);
+        self.closures.insert(name.into(), closure);
+        Ok(())
+    }
+
+    fn add_property(&mut self, name: &str, spec: &Value) -> Result<(), LabeledError> {
+        dbus::strings::Member::new(name).map_err(|err| {
+            LabeledError::new("Invalid property name").with_label(err, spec.span())
+        })?;
+
+        let mut property = Property {
+            name: name.into(),
+            r#type: String::new(),
+            access: Access::Read,
+            annotations: vec![],
+        };
+        let mut value = None;
+        for (key, field) in spec.as_record()? {
+            match &key[..] {
+                "type" => {
+                    let args = parse_args(field, Direction::In)?;
+                    let [arg] = &args[..] else {
+                        return Err(LabeledError::new("Invalid property type")
+                            .with_label("expected exactly one type", field.span()));
+                    };
+                    property.r#type = arg.r#type.clone();
+                }
+                "access" => {
+                    property.access = match field.as_str()? {
+                        "read" => Access::Read,
+                        "write" => Access::Write,
+                        "readwrite" => Access::ReadWrite,
+                        _ => {
+                            return Err(LabeledError::new("Invalid property access").with_label(
+                                "expected `read`, `write`, or `readwrite`",
+                                field.span(),
+                            ))
+                        }
+                    }
+                }
+                "value" => value = Some(field.clone()),
+                _ => {
+                    return Err(LabeledError::new(format!(
+                        "Unknown key {key:?} in the description of property {name}"
+                    ))
+                    .with_label("expected `type`, `access`, or `value`", field.span()))
+                }
+            }
+        }
+
+        if property.r#type.is_empty() {
+            return Err(LabeledError::new(format!("Property {name} has no type"))
+                .with_label("add the `type` of this property", spec.span()));
+        }
+        match value {
+            Some(value) => {
+                // Make sure it can be sent later
+                let r#type = DbusType::parse_all(&property.r#type).map_err(LabeledError::new)?;
+                convert::to_message_item(&value, r#type.first())?;
+                self.values.insert(name.into(), value);
+            }
+            None if property.access != Access::Write => {
+                return Err(LabeledError::new(format!("Property {name} has no value"))
+                    .with_label("add the initial `value` of this property", spec.span()));
+            }
+            None => (),
+        }
+        self.spec.properties.push(property);
+        Ok(())
+    }
+
+    fn add_signal(&mut self, name: &str, args: &Value) -> Result<(), LabeledError> {
+        dbus::strings::Member::new(name)
+            .map_err(|err| LabeledError::new("Invalid signal name").with_label(err, args.span()))?;
+
+        self.spec.signals.push(Signal {
+            name: name.into(),
+            args: parse_args(args, Direction::Out)?
+                .into_iter()
+                .map(|arg| SignalArg {
+                    name: arg.name,
+                    r#type: arg.r#type,
+                })
+                .collect(),
+            annotations: vec![],
+        });
+        Ok(())
+    }
+}
+
+/// Parse arguments given either as a signature string, or as a record of names to types
+fn parse_args(value: &Value, direction: Direction) -> Result<Vec<MethodArg>, LabeledError> {
+    let invalid =
+        |err: String| LabeledError::new("Invalid signature").with_label(err, value.span());
+    match value {
+        Value::String { val, .. } => Ok(DbusType::parse_all(val)
+            .map_err(invalid)?
+            .into_iter()
+            .map(|r#type| MethodArg {
+                name: None,
+                r#type: r#type.stringify(),
+                direction,
+            })
+            .collect()),
+        Value::Record { val, .. } => val
+            .iter()
+            .map(|(name, r#type)| {
+                let (_, rest) = DbusType::parse(r#type.as_str()?).map_err(invalid)?;
+                if !rest.is_empty() {
+                    return Err(invalid(format!("{name} must have exactly one type")));
+                }
+                Ok(MethodArg {
+                    name: Some(name.clone()),
+                    r#type: r#type.as_str()?.into(),
+                    direction,
+                })
+            })
+            .collect(),
+        _ => Err(LabeledError::new("Invalid arguments")
+            .with_label("expected a signature or a record of types", value.span())),
+    }
+}
+
+/// Convert the value of a property to a variant of the property's type
+fn encode_property(property: &Property, value: &Value) -> Result<MessageItem, MethodError> {
+    let r#type =
+        DbusType::parse_all(&property.r#type).map_err(|err| MethodError::new("Failed", err))?;
+    convert::to_message_item(value, r#type.first())
+        .map(|item| MessageItem::Variant(Box::new(item)))
+        .map_err(|err| MethodError::new("Failed", err.msg))
+}
+
+/// Convert the return value of a method closure to the method's output arguments
+fn encode_reply(method: &Method, result: Value) -> Result<Vec<MessageItem>, MethodError> {
+    let failed = |err: LabeledError| MethodError::new("Failed", err.msg);
+    let out_types = DbusType::parse_all(&method.out_signature())
+        .map_err(|err| MethodError::new("Failed", err))?;
+
+    // Like the client side, a single return value isn't wrapped in a list
+    let values = match out_types.len() {
+        0 => vec![],
+        1 => vec![result],
+        len => {
+            let values = result.into_list().map_err(|err| failed(err.into()))?;
+            if values.len() != len {
+                return Err(MethodError::new(
+                    "Failed",
+                    format!("Expected {len} return values, got {}", values.len()),
+                ));
+            }
+            values
+        }
+    };
+
+    values
+        .iter()
+        .zip(&out_types)
+        .map(|(value, r#type)| convert::to_message_item(value, Some(r#type)).map_err(failed))
+        .collect()
+}
+
+#[cfg(test)]
+fn test_method(in_signature: &str, out_signature: &str) -> Method {
+    let mut args = parse_args(&Value::test_string(in_signature), Direction::In).unwrap();
+    args.extend(parse_args(&Value::test_string(out_signature), Direction::Out).unwrap());
+    Method {
+        name: "Test".into(),
+        args,
+        annotations: vec![],
+    }
+}
+
+#[test]
+fn test_parse_args() {
+    let args = parse_args(&Value::test_string("sa{sv}(ii)"), Direction::In).unwrap();
+    let types = args.iter().map(|a| &a.r#type[..]).collect::<Vec<_>>();
+    assert_eq!(vec!["s", "a{sv}", "(ii)"], types);
+
+    let args = parse_args(
+        &Value::test_record(record!(
+            "name" => Value::test_string("s"),
+            "flags" => Value::test_string("u"),
+        )),
+        Direction::Out,
+    )
+    .unwrap();
+    assert_eq!(Some("name"), args[0].name.as_deref());
+    assert_eq!("u", args[1].r#type);
+    assert_eq!(Direction::Out, args[1].direction);
+
+    assert!(parse_args(&Value::test_string("a"), Direction::In).is_err());
+    assert!(parse_args(
+        &Value::test_record(record!("both" => Value::test_string("ss"))),
+        Direction::In
+    )
+    .is_err());
+}
+
+#[test]
+fn test_from_value_errors() {
+    let method = |spec: Value| {
+        Value::test_record(record!(
+            "/com/example" => Value::test_record(record!(
+                "com.example.Test" => Value::test_record(record!(
+                    "methods" => Value::test_record(record!("Test" => spec)),
+                )),
+            )),
+        ))
+    };
+    // No closure
+    assert!(ObjectTree::from_value(&method(Value::test_record(record!(
+        "in" => Value::test_string("s"),
+    ))))
+    .is_err());
+    // Unknown key
+    assert!(ObjectTree::from_value(&method(Value::test_record(record!(
+        "inn" => Value::test_string("s"),
+    ))))
+    .is_err());
+    // Bad path
+    assert!(ObjectTree::from_value(&Value::test_record(record!(
+        "com/example" => Value::test_record(record!()),
+    )))
+    .is_err());
+}
+
+#[cfg(test)]
+fn test_properties() -> Vec<ServedInterface> {
+    let property = |r#type: &str, access: &str, value: Option<Value>| {
+        let mut spec = record!(
+            "type" => Value::test_string(r#type),
+            "access" => Value::test_string(access),
+        );
+        if let Some(value) = value {
+            spec.insert("value", value);
+        }
+        Value::test_record(spec)
+    };
+    let spec = Value::test_record(record!(
+        "properties" => Value::test_record(record!(
+            "Count" => property("u", "readwrite", Some(Value::test_int(1))),
+            "Name" => property("s", "read", Some(Value::test_string("test"))),
+            "Secret" => property("s", "write", None),
+        )),
+        "signals" => Value::test_record(record!(
+            "Changed" => Value::test_string("s"),
+        )),
+    ));
+    vec![ServedInterface::from_value("com.example.Test", &spec).unwrap()]
+}
+
+#[cfg(test)]
+fn test_properties_call(member: &str) -> Message {
+    let mut call =
+        Message::new_method_call("com.example", "/com/example", PROPERTIES, member).unwrap();
+    call.set_serial(1);
+    call
+}
+
+#[test]
+fn test_introspect() -> Result<(), MethodError> {
+    let node = Node::from_xml(&introspect(&test_properties(), &[], false)?).unwrap();
+    let names = node
+        .interfaces
+        .iter()
+        .map(|i| &i.name[..])
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec!["com.example.Test", INTROSPECTABLE, PROPERTIES, PEER],
+        names
+    );
+    assert_eq!(3, node.interfaces[0].properties.len());
+    assert_eq!("s", node.interfaces[0].signals[0].args[0].r#type);
+    Ok(())
+}
+
+#[test]
+fn test_signal_signature() {
+    let tree = ObjectTree {
+        objects: [("/com/example".into(), test_properties())].into(),
+        object_manager: None,
+    };
+    assert_eq!(
+        Some("s".into()),
+        tree.signal_signature("/com/example", "com.example.Test", "Changed")
+    );
+    assert_eq!(
+        None,
+        tree.signal_signature("/com/example", "com.example.Test", "Nope")
+    );
+    assert_eq!(
+        None,
+        tree.signal_signature("/com", "com.example.Test", "Changed")
+    );
+}
+
+#[test]
+fn test_is_below() {
+    assert!(is_below("/com/example", "/com"));
+    assert!(is_below("/com", "/"));
+    assert!(!is_below("/com", "/com"));
+    assert!(!is_below("/comet", "/com"));
+    assert!(!is_below("/", "/"));
+}
+
+#[test]
+fn test_object_manager() -> Result<(), MethodError> {
+    let mut tree = ObjectTree::default();
+    let span = Span::test_data();
+    let path = |path: &str| Spanned {
+        item: path.to_string(),
+        span,
+    };
+    let object = Value::test_record(record!(
+        "com.example.Empty" => Value::test_record(record!()),
+    ));
+    tree.set_object_manager(&path("/com/example")).unwrap();
+    tree.add_object(&path("/com/example/a/1"), &object).unwrap();
+    tree.add_object(&path("/com/example/b"), &object).unwrap();
+    assert!(tree.add_object(&path("/com/example/b"), &object).is_err());
+    assert_eq!(vec!["a", "b"], tree.children("/com/example"));
+    assert_eq!(vec!["example"], tree.children("/com"));
+
+    let added = tree.add_object(&path("/com/example/c"), &object).unwrap();
+    let added = added.expect("InterfacesAdded wasn't emitted");
+    assert_eq!(Some("InterfacesAdded"), added.member().as_deref());
+    assert_eq!(Some("/com/example"), added.path().as_deref());
+    assert!(tree.add_object(&path("/other"), &object).unwrap().is_none());
+
+    let removed = tree.remove_object(&path("/com/example/c")).unwrap();
+    let removed = removed.expect("InterfacesRemoved wasn't emitted");
+    let (object, names) = removed.read2::<dbus::Path, Vec<&str>>().unwrap();
+    assert_eq!("/com/example/c", &*object);
+    assert_eq!(vec!["com.example.Empty"], names);
+    assert!(tree.remove_object(&path("/com/example/c")).is_err());
+
+    let mut call = Message::new_method_call(
+        "com.example",
+        "/com/example",
+        OBJECT_MANAGER,
+        "GetManagedObjects",
+    )
+    .unwrap();
+    call.set_serial(1);
+    let Dispatch::Reply(reply, _) = tree.dispatch(&call, span)? else {
+        panic!("GetManagedObjects wasn't handled by the tree");
+    };
+    let objects = convert::from_message(&reply, span).unwrap();
+    let mut paths = objects[0]
+        .as_record()
+        .unwrap()
+        .columns()
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(vec!["/com/example/a/1", "/com/example/b"], paths);
+    Ok(())
+}
+
+#[test]
+fn test_get_property() -> Result<(), MethodError> {
+    let mut interfaces = test_properties();
+    let span = Span::test_data();
+
+    let call = test_properties_call("Get").append2("com.example.Test", "Count");
+    let (reply, _) = handle_properties(&mut interfaces, &call, "Get", span)?;
+    assert_eq!(Some(1u32), reply.get1::<Variant<u32>>().map(|v| v.0));
+
+    let call = test_properties_call("Get").append2("com.example.Test", "Secret");
+    let err = handle_properties(&mut interfaces, &call, "Get", span).unwrap_err();
+    assert_eq!("org.freedesktop.DBus.Error.AccessDenied", err.name);
+
+    let call = test_properties_call("Get").append2("com.example.Test", "Nope");
+    let err = handle_properties(&mut interfaces, &call, "Get", span).unwrap_err();
+    assert_eq!("org.freedesktop.DBus.Error.UnknownProperty", err.name);
+
+    let call = test_properties_call("GetAll").append1("com.example.Test");
+    let (reply, _) = handle_properties(&mut interfaces, &call, "GetAll", span)?;
+    let all = convert::from_message(&reply, span).unwrap();
+    let mut names = all[0].as_record().unwrap().columns().collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(vec!["Count", "Name"], names);
+    Ok(())
+}
+
+#[test]
+fn test_set_property() -> Result<(), MethodError> {
+    let mut interfaces = test_properties();
+    let span = Span::test_data();
+
+    let call = test_properties_call("Set").append3("com.example.Test", "Count", Variant(5u32));
+    let (_, signals) = handle_properties(&mut interfaces, &call, "Set", span)?;
+    assert_eq!(1, signals.len());
+    assert_eq!(Some("PropertiesChanged"), signals[0].member().as_deref());
+    let (interface, changed) = signals[0]
+        .read2::<&str, std::collections::HashMap<String, Variant<u32>>>()
+        .unwrap();
+    assert_eq!("com.example.Test", interface);
+    assert_eq!(5, changed["Count"].0);
+
+    let call = test_properties_call("Get").append2("com.example.Test", "Count");
+    let (reply, _) = handle_properties(&mut interfaces, &call, "Get", span)?;
+    assert_eq!(Some(5u32), reply.get1::<Variant<u32>>().map(|v| v.0));
+
+    let call = test_properties_call("Set").append3("com.example.Test", "Count", Variant("five"));
+    let err = handle_properties(&mut interfaces, &call, "Set", span).unwrap_err();
+    assert_eq!("org.freedesktop.DBus.Error.InvalidArgs", err.name);
+
+    let call = test_properties_call("Set").append3("com.example.Test", "Name", Variant("other"));
+    let err = handle_properties(&mut interfaces, &call, "Set", span).unwrap_err();
+    assert_eq!("org.freedesktop.DBus.Error.PropertyReadOnly", err.name);
+    Ok(())
+}
+
+#[test]
+fn test_encode_reply() {
+    assert_eq!(
+        Ok(vec![]),
+        encode_reply(&test_method("s", ""), Value::test_string("ignored"))
+    );
+    assert_eq!(
+        Ok(vec![MessageItem::UInt32(5)]),
+        encode_reply(&test_method("", "u"), Value::test_int(5))
+    );
+    assert_eq!(
+        Ok(vec![MessageItem::Str("a".into()), MessageItem::Int32(-1)]),
+        encode_reply(
+            &test_method("", "si"),
+            Value::test_list(vec![Value::test_string("a"), Value::test_int(-1)])
+        )
+    );
+    let err = encode_reply(
+        &test_method("", "si"),
+        Value::test_list(vec![Value::test_string("a")]),
+    )
+    .unwrap_err();
+    assert_eq!("org.freedesktop.DBus.Error.Failed", err.name);
+}